@@ -0,0 +1,365 @@
+use std::fmt;
+use std::ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, BitXorAssign, Not, Sub, SubAssign};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Fingerprint};
+
+const WORDS: usize = 256;
+
+/// A fingerprint of the default 128x128 retina packed into 256 `u64` words, one bit per position.
+///
+/// The set operations and counts work a word at a time, so comparing two fingerprints costs a few
+/// hundred popcounts instead of expanding both to dense vectors. Serializes like a [`Fingerprint`].
+#[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "Fingerprint", into = "Fingerprint")]
+pub struct BitFingerprint {
+    words: [u64; WORDS],
+}
+
+impl Default for BitFingerprint {
+    fn default() -> Self {
+        Self {
+            words: [0; WORDS],
+        }
+    }
+}
+
+impl BitFingerprint {
+    /// The number of positions a bitset holds.
+    pub const LEN: usize = WORDS * 64;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_words(words: [u64; WORDS]) -> Self {
+        Self {
+            words,
+        }
+    }
+
+    pub fn words(&self) -> &[u64; WORDS] {
+        &self.words
+    }
+
+    /// Sets `position`, returning whether it was unset before.
+    pub fn insert(&mut self, position: u32) -> Result<bool, Error> {
+        if position as usize >= Self::LEN {
+            return Err(Error::PositionOutOfRange { position, len: Self::LEN });
+        }
+
+        let (word, bit) = (position as usize / 64, 1u64 << (position % 64));
+        let inserted = self.words[word] & bit == 0;
+
+        self.words[word] |= bit;
+
+        Ok(inserted)
+    }
+
+    /// Clears `position`, returning whether it was set.
+    pub fn remove(&mut self, position: u32) -> bool {
+        let removed = self.contains(position);
+
+        if removed {
+            self.words[position as usize / 64] &= !(1u64 << (position % 64));
+        }
+
+        removed
+    }
+
+    pub fn contains(&self, position: u32) -> bool {
+        self.words
+            .get(position as usize / 64)
+            .map(|word| word & (1u64 << (position % 64)) != 0)
+            .unwrap_or(false)
+    }
+
+    /// The number of set positions.
+    #[inline]
+    pub fn len(&self) -> u32 {
+        self.words
+            .iter()
+            .map(|word| word.count_ones())
+            .sum()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|word| *word == 0)
+    }
+
+    /// |A ∩ B|
+    #[inline]
+    pub fn overlap(&self, other: &BitFingerprint) -> u32 {
+        self.words
+            .iter()
+            .zip(other.words.iter())
+            .map(|(l, r)| (l & r).count_ones())
+            .sum()
+    }
+
+    /// |A ∪ B|
+    #[inline]
+    pub fn union_len(&self, other: &BitFingerprint) -> u32 {
+        self.words
+            .iter()
+            .zip(other.words.iter())
+            .map(|(l, r)| (l | r).count_ones())
+            .sum()
+    }
+
+    /// |A ∩ B| / |A ∪ B|, or 0 when both are empty.
+    #[inline]
+    pub fn jaccard(&self, other: &BitFingerprint) -> f64 {
+        let union = self.union_len(other);
+
+        if union == 0 {
+            0.0
+        } else {
+            self.overlap(other) as f64 / union as f64
+        }
+    }
+
+    /// The set positions in ascending order.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            words: &self.words,
+            index: 0,
+            current: self.words[0],
+        }
+    }
+}
+
+impl fmt::Debug for BitFingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set()
+            .entries(self.iter())
+            .finish()
+    }
+}
+
+/// Iterates the set positions of a [`BitFingerprint`].
+pub struct Iter<'a> {
+    words: &'a [u64; WORDS],
+    index: usize,
+    current: u64,
+}
+
+impl Iterator for Iter<'_> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        while self.current == 0 {
+            self.index += 1;
+            self.current = *self.words.get(self.index)?;
+        }
+
+        let bit = self.current.trailing_zeros();
+
+        // clear the lowest set bit
+        self.current &= self.current - 1;
+
+        Some((self.index * 64) as u32 + bit)
+    }
+}
+
+impl<'a> IntoIterator for &'a BitFingerprint {
+    type Item = u32;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+impl TryFrom<&Fingerprint> for BitFingerprint {
+    type Error = Error;
+
+    fn try_from(fingerprint: &Fingerprint) -> Result<Self, Error> {
+        let mut bits = BitFingerprint::new();
+
+        for position in fingerprint.positions.iter() {
+            bits.insert(*position)?;
+        }
+
+        Ok(bits)
+    }
+}
+
+impl TryFrom<Fingerprint> for BitFingerprint {
+    type Error = Error;
+
+    fn try_from(fingerprint: Fingerprint) -> Result<Self, Error> {
+        BitFingerprint::try_from(&fingerprint)
+    }
+}
+
+impl From<&BitFingerprint> for Fingerprint {
+    fn from(bits: &BitFingerprint) -> Self {
+        Fingerprint {
+            positions: bits.iter().collect(),
+        }
+    }
+}
+
+impl From<BitFingerprint> for Fingerprint {
+    fn from(bits: BitFingerprint) -> Self {
+        Fingerprint::from(&bits)
+    }
+}
+
+/// Implements a binary operator word by word for every combination of owned and borrowed
+/// operands, along with its assigning form.
+macro_rules! word_op {
+    ($op:ident, $method:ident, $assign:ident, $assign_method:ident, |$l:ident, $r:ident| $body:expr) => {
+        impl $assign<&BitFingerprint> for BitFingerprint {
+            fn $assign_method(&mut self, other: &BitFingerprint) {
+                for (word, other) in self.words.iter_mut().zip(other.words.iter()) {
+                    let ($l, $r) = (*word, *other);
+                    *word = $body;
+                }
+            }
+        }
+
+        impl $assign for BitFingerprint {
+            fn $assign_method(&mut self, other: BitFingerprint) {
+                self.$assign_method(&other);
+            }
+        }
+
+        impl $op<&BitFingerprint> for &BitFingerprint {
+            type Output = BitFingerprint;
+
+            fn $method(self, other: &BitFingerprint) -> BitFingerprint {
+                let mut result = self.clone();
+                result.$assign_method(other);
+                result
+            }
+        }
+
+        impl $op<&BitFingerprint> for BitFingerprint {
+            type Output = BitFingerprint;
+
+            fn $method(mut self, other: &BitFingerprint) -> BitFingerprint {
+                self.$assign_method(other);
+                self
+            }
+        }
+
+        impl $op for BitFingerprint {
+            type Output = BitFingerprint;
+
+            fn $method(mut self, other: BitFingerprint) -> BitFingerprint {
+                self.$assign_method(&other);
+                self
+            }
+        }
+    };
+}
+
+word_op!(BitAnd, bitand, BitAndAssign, bitand_assign, |l, r| l & r);
+word_op!(BitOr, bitor, BitOrAssign, bitor_assign, |l, r| l | r);
+word_op!(BitXor, bitxor, BitXorAssign, bitxor_assign, |l, r| l ^ r);
+word_op!(Sub, sub, SubAssign, sub_assign, |l, r| l & !r);
+
+impl Not for &BitFingerprint {
+    type Output = BitFingerprint;
+
+    fn not(self) -> BitFingerprint {
+        !self.clone()
+    }
+}
+
+impl Not for BitFingerprint {
+    type Output = BitFingerprint;
+
+    fn not(mut self) -> BitFingerprint {
+        self.words
+            .iter_mut()
+            .for_each(|word| *word = !*word);
+
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Error, Fingerprint};
+
+    use super::BitFingerprint;
+
+    fn bits(positions: &[u32]) -> BitFingerprint {
+        BitFingerprint::try_from(&Fingerprint { positions: positions.to_vec() }).unwrap()
+    }
+
+    #[test]
+    fn round_trips_fingerprints() {
+        let fingerprint = Fingerprint { positions: vec![0, 63, 64, 127, 9000, 16383] };
+
+        let packed = BitFingerprint::try_from(&fingerprint).unwrap();
+
+        assert_eq!(packed.len(), 6);
+        assert!(packed.contains(9000));
+        assert!(!packed.contains(9001));
+        assert!(!packed.contains(20000));
+        assert_eq!(Fingerprint::from(&packed), fingerprint);
+        assert_eq!(format!("{:?}", bits(&[3, 1])), "{1, 3}");
+
+        assert!(matches!(
+            BitFingerprint::try_from(Fingerprint { positions: vec![16384] }),
+            Err(Error::PositionOutOfRange { position: 16384, len: 16384 })
+        ));
+    }
+
+    #[test]
+    fn insert_and_remove() {
+        let mut packed = BitFingerprint::new();
+
+        assert!(packed.insert(70).unwrap());
+        assert!(!packed.insert(70).unwrap());
+        assert!(packed.remove(70));
+        assert!(!packed.remove(70));
+        assert!(packed.is_empty());
+    }
+
+    #[test]
+    fn set_algebra() {
+        let left = bits(&[1, 2, 3, 100]);
+        let right = bits(&[3, 100, 200]);
+
+        assert_eq!(Fingerprint::from(&left & &right).positions, vec![3, 100]);
+        assert_eq!(Fingerprint::from(&left | &right).positions, vec![1, 2, 3, 100, 200]);
+        assert_eq!(Fingerprint::from(&left ^ &right).positions, vec![1, 2, 200]);
+        assert_eq!(Fingerprint::from(&left - &right).positions, vec![1, 2]);
+        assert_eq!((!&left).len(), 16380);
+        assert_eq!(!!left.clone(), left);
+
+        let mut assigned = left.clone();
+        assigned &= right.clone();
+
+        assert_eq!(assigned, left & right);
+    }
+
+    #[test]
+    fn counts_agree_with_compare() {
+        let left = Fingerprint { positions: (0..506).collect() };
+        let right = Fingerprint { positions: (291..827).collect() };
+
+        let (packed_left, packed_right) = (BitFingerprint::try_from(&left).unwrap(), BitFingerprint::try_from(&right).unwrap());
+        let compare = left.compare_full(&right).unwrap();
+
+        assert_eq!(packed_left.overlap(&packed_right), compare.overlapping_all);
+        assert_eq!(packed_left.union_len(&packed_right), 827);
+        assert!((packed_left.jaccard(&packed_right) - (1.0 - compare.jaccard_distance)).abs() < 1e-12);
+        assert_eq!(BitFingerprint::new().jaccard(&BitFingerprint::new()), 0.0);
+    }
+
+    #[test]
+    fn serializes_as_positions() {
+        let packed = bits(&[5, 8]);
+
+        assert_eq!(serde_json::to_string(&packed).unwrap(), r#"{"positions":[5,8]}"#);
+        assert_eq!(serde_json::from_str::<BitFingerprint>(r#"{"positions":[8,5]}"#).unwrap(), packed);
+    }
+}
@@ -1,134 +1,401 @@
-use std::error::Error;
+use std::time::Duration;
 
-use crate::{CompareResponse, CreateCategoryFilterRequest, CreateCategoryFilterResponse, Fingerprint, GetTermsContextsRequest, GetTermsContextsResponse, GetTermsRequest, GetTermsResponse, GetTermsSimilarTermsRequest, LanguageResponse, PosType, Retina, TextEnvelope, TextSlice, TextSliceRequest};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use futures_util::stream::{self, StreamExt, TryStreamExt};
+use reqwest::{Method, RequestBuilder};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 
+use crate::expression::{Expression, GetExpressionContextsRequest, GetExpressionRequest, GetExpressionSimilarTermsRequest};
+use crate::rate_limit::RateLimiter;
+use crate::retry::RetryPolicy;
+use crate::{ApiError, CompareResponse, CreateCategoryFilterRequest, CreateCategoryFilterResponse, Error, Fingerprint, GetImageRequest, GetTermsContextsRequest, GetTermsContextsResponse, GetTermsRequest, GetTermsResponse, GetTermsSimilarTermsRequest, ImageData, ImageEncoding, LanguageResponse, PosType, Retina, TextEnvelope, TextSlice, TextSliceRequest};
+
+const DEFAULT_BASE_URL: &str = "https://languages.cortical.io";
+
+const DEFAULT_BULK_BATCH_SIZE: usize = 100;
+
+const DEFAULT_BULK_CONCURRENCY: usize = 4;
+
+const REQUEST_ID_HEADERS: [&str; 2] = ["x-request-id", "x-correlation-id"];
+
+#[derive(Clone)]
 pub struct Cortical {
     pub client: reqwest::Client,
     pub base_url: String,
+    pub headers: HeaderMap,
+    pub timeout: Option<Duration>,
+    pub retry: RetryPolicy,
+    pub limiter: RateLimiter,
+    /// Maximum number of inputs sent in one request to a bulk endpoint.
+    pub bulk_batch_size: usize,
+    /// Maximum number of bulk batches sent concurrently.
+    pub bulk_concurrency: usize,
+}
+
+impl Default for Cortical {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Configures a [`Cortical`] client.
+///
+/// Unset values fall back to the `CORTICAL_API_URL` and `CORTICAL_API_KEY` environment variables.
+#[derive(Default)]
+pub struct CorticalBuilder {
+    base_url: Option<String>,
+    api_key: Option<String>,
+    bearer_token: Option<String>,
+    user_agent: Option<String>,
+    headers: Vec<(String, String)>,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    client: Option<reqwest::Client>,
+    retry: Option<RetryPolicy>,
+    limiter: RateLimiter,
+    bulk_batch_size: Option<usize>,
+    bulk_concurrency: Option<usize>,
+    skip_invalid_env_api_key: bool,
+}
+
+impl CorticalBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = Some(base_url.to_string());
+        self
+    }
+
+    /// Sent as the `api-key` header, as expected by licensed Retina API installations.
+    pub fn with_api_key(mut self, api_key: &str) -> Self {
+        self.api_key = Some(api_key.to_string());
+        self
+    }
+
+    /// Sent as `Authorization: Bearer <token>`.
+    pub fn with_bearer_token(mut self, token: &str) -> Self {
+        self.bearer_token = Some(token.to_string());
+        self
+    }
+
+    pub fn with_user_agent(mut self, user_agent: &str) -> Self {
+        self.user_agent = Some(user_agent.to_string());
+        self
+    }
+
+    /// Adds a header sent with every request, replacing a default header of the same name.
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Total time allowed per request, including reading the response.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Ignored when a custom client is supplied with [`CorticalBuilder::with_client`].
+    pub fn with_connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = Some(connect_timeout);
+        self
+    }
+
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Limits every clone of the client to `requests_per_second` on average, with bursts of up
+    /// to `burst` requests. Each retry attempt counts as a request.
+    pub fn with_rate_limit(mut self, requests_per_second: f64, burst: u32) -> Self {
+        self.limiter = self.limiter.with_rate(requests_per_second, burst);
+        self
+    }
+
+    /// Caps the number of requests in flight across every clone of the client.
+    pub fn with_max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.limiter = self.limiter.with_max_in_flight(max_in_flight);
+        self
+    }
+
+    pub fn with_bulk_batch_size(mut self, bulk_batch_size: usize) -> Self {
+        self.bulk_batch_size = Some(bulk_batch_size.max(1));
+        self
+    }
+
+    pub fn with_bulk_concurrency(mut self, bulk_concurrency: usize) -> Self {
+        self.bulk_concurrency = Some(bulk_concurrency.max(1));
+        self
+    }
+
+    /// Requests are sent once unless a retry policy is configured.
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    pub fn build(self) -> Result<Cortical, Error> {
+        let base_url =
+            self.base_url
+                .or_else(|| std::env::var("CORTICAL_API_URL").ok())
+                .unwrap_or_else(|| DEFAULT_BASE_URL.to_string());
+
+        let api_key =
+            api_key_header(
+                self.api_key,
+                std::env::var("CORTICAL_API_KEY").ok(),
+                self.skip_invalid_env_api_key,
+            )?;
+
+        let mut headers = HeaderMap::new();
+
+        headers.insert("Accept", HeaderValue::from_static("application/json"));
+        headers.insert("Referer", HeaderValue::from_static(""));
+
+        if let Some(api_key) = api_key {
+            headers.insert("api-key", api_key);
+        }
+
+        if let Some(token) = self.bearer_token {
+            headers.insert("Authorization", header_value(&format!("Bearer {}", token))?);
+        }
+
+        if let Some(user_agent) = self.user_agent {
+            headers.insert("User-Agent", header_value(&user_agent)?);
+        }
+
+        for (name, value) in self.headers.iter() {
+            let name =
+                HeaderName::from_bytes(name.as_bytes())
+                    .map_err(|_| Error::Config(format!("invalid header name: {}", name)))?;
+
+            headers.insert(name, header_value(value)?);
+        }
+
+        let client =
+            match self.client {
+                Some(client) => client,
+                None => {
+                    let mut builder = reqwest::Client::builder();
+
+                    if let Some(connect_timeout) = self.connect_timeout {
+                        builder = builder.connect_timeout(connect_timeout);
+                    }
+
+                    builder.build()?
+                }
+            };
+
+        Ok(
+            Cortical {
+                client,
+                base_url: base_url.trim_end_matches('/').to_string(),
+                headers,
+                timeout: self.timeout,
+                retry: self.retry.unwrap_or_else(RetryPolicy::none),
+                limiter: self.limiter,
+                bulk_batch_size: self.bulk_batch_size.unwrap_or(DEFAULT_BULK_BATCH_SIZE),
+                bulk_concurrency: self.bulk_concurrency.unwrap_or(DEFAULT_BULK_CONCURRENCY),
+            }
+        )
+    }
+}
+
+fn header_value(value: &str) -> Result<HeaderValue, Error> {
+    HeaderValue::from_str(value)
+        .map_err(|_| Error::Config(format!("invalid header value: {:?}", value)))
+}
+
+/// The `api-key` header for `api_key`, or else for the one from the environment. With
+/// `skip_invalid_env`, an environment value that is not a valid header value is left out instead
+/// of failing.
+fn api_key_header(api_key: Option<String>, env_api_key: Option<String>, skip_invalid_env: bool) -> Result<Option<HeaderValue>, Error> {
+    match (api_key, env_api_key) {
+        (Some(api_key), _) => header_value(&api_key).map(Some),
+        (None, Some(api_key)) if skip_invalid_env => Ok(HeaderValue::from_str(&api_key).ok()),
+        (None, Some(api_key)) => header_value(&api_key).map(Some),
+        (None, None) => Ok(None),
+    }
 }
 
 impl Cortical {
+    /// A client configured from the `CORTICAL_API_URL` and `CORTICAL_API_KEY` environment
+    /// variables. A `CORTICAL_API_KEY` that is not a valid header value is left out, so requests
+    /// go unauthenticated; use [`Cortical::builder`] to get an error for it instead.
+    ///
+    /// # Panics
+    ///
+    /// Like `reqwest::Client::new`, if the TLS backend can't be initialized.
     pub fn new() -> Cortical {
-        Cortical {
-            client: reqwest::Client::new(),
-            base_url: std::env::var("CORTICAL_API_URL").unwrap_or("https://languages.cortical.io".to_string()),
-        }
+        let builder =
+            CorticalBuilder {
+                skip_invalid_env_api_key: true,
+                ..Self::builder()
+            };
+
+        builder
+            .build()
+            .expect("a client without explicit settings only fails to build when reqwest does")
     }
 
-    pub async fn get_retinas(&self) -> Result<Vec<Retina>, Box<dyn Error>> {
-        let response =
+    pub fn builder() -> CorticalBuilder {
+        CorticalBuilder::new()
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let request =
             self.client
-                .get(format!("{}{}", &self.base_url, "/rest/retinas"))
-                .send()
+                .request(method, format!("{}{}", &self.base_url, path))
+                .headers(self.headers.clone());
+
+        match self.timeout {
+            Some(timeout) => request.timeout(timeout),
+            None => request,
+        }
+    }
+
+    /// Sends the request, retrying as the retry policy allows, and decodes the JSON response.
+    async fn execute<T: DeserializeOwned>(&self, request: RequestBuilder, idempotent: bool) -> Result<T, Error> {
+        let payload = self.execute_raw(request, idempotent).await?;
+
+        serde_json::from_slice(&payload)
+            .map_err(|source| Error::Deserialize { source, payload: String::from_utf8_lossy(&payload).into_owned() })
+    }
+
+    /// Sends the request, retrying as the retry policy allows, and returns the response body.
+    async fn execute_raw(&self, request: RequestBuilder, idempotent: bool) -> Result<Vec<u8>, Error> {
+        let mut attempt = 1;
+
+        loop {
+            let retryable = self.retry.allows_retry(attempt, idempotent);
+            let permit = self.limiter.acquire().await;
+
+            let pending =
+                match request.try_clone() {
+                    Some(pending) if retryable => pending,
+                    _ => return read_body(request.send().await?).await,
+                };
+
+            let delay =
+                match pending.send().await {
+                    Ok(response) if self.retry.retries_status(response.status().as_u16()) =>
+                        self.retry.delay(attempt, Some(response.headers())),
+                    Ok(response) => return read_body(response).await,
+                    Err(err) if self.retry.retries_error(&err) => self.retry.delay(attempt, None),
+                    Err(err) => return Err(err.into()),
+                };
+
+            drop(permit);
+
+            tokio::time::sleep(delay).await;
+
+            attempt += 1;
+        }
+    }
+
+    /// Splits `items` into batches of `bulk_batch_size`, posts them concurrently and returns the
+    /// results in input order.
+    async fn execute_bulk<I: Serialize, T: DeserializeOwned>(
+        &self,
+        request: impl Fn() -> RequestBuilder,
+        items: &[I],
+    ) -> Result<Vec<T>, Error> {
+        let batches =
+            stream::iter(items.chunks(self.bulk_batch_size.max(1)))
+                .map(|batch| {
+                    let request =
+                        serde_json::to_string(batch)
+                            .map_err(Error::Serialize)
+                            .map(|body| {
+                                request()
+                                    .header("Content-Type", "application/json")
+                                    .body(body)
+                            });
+
+                    async move {
+                        let results: Vec<T> = self.execute(request?, true).await?;
+
+                        if results.len() != batch.len() {
+                            return Err(Error::BulkLength { expected: batch.len(), actual: results.len() });
+                        }
+
+                        Ok(results)
+                    }
+                })
+                .buffered(self.bulk_concurrency.max(1))
+                .try_collect::<Vec<Vec<T>>>()
                 .await?;
 
-        Ok(
-            response
-                .json()
-                .await?
-        )
+        Ok(batches.into_iter().flatten().collect())
+    }
+
+    pub async fn get_retinas(&self) -> Result<Vec<Retina>, Error> {
+        let request =
+            self.request(Method::GET, "/rest/retinas");
+
+        self.execute(request, true).await
     }
 
     pub async fn get_text_analysis(
         &self,
         text: &str,
         retina_name: Option<&str>,
-    ) -> Result<Vec<Fingerprint>, Box<dyn Error>> {
+    ) -> Result<Vec<Fingerprint>, Error> {
         let retina_name = retina_name.unwrap_or("en_general");
 
-        let response =
-            self.client
-                .post(format!("{}/rest/text?retina_name={}", &self.base_url, retina_name))
-                .header("Accept", "application/json")
-                .header("Referer", "")
+        let request =
+            self.request(Method::POST, &format!("/rest/text?retina_name={}", retina_name))
                 .header("Content-Type", "application/json")
-                .body(text.to_string())
-                .send()
-                .await?;
+                .body(text.to_string());
 
-        Ok(
-            response
-                .json()
-                .await?
-        )
+        self.execute(request, true).await
     }
 
     pub async fn get_text_keywords(
         &self,
         text: &str,
         retina_name: Option<&str>,
-    ) -> Result<Vec<String>, Box<dyn Error>> {
+    ) -> Result<Vec<String>, Error> {
         let retina_name = retina_name.unwrap_or("en_general");
 
-        let response =
-            self.client
-                .post(format!("{}/rest/text/keywords?retina_name={}", &self.base_url, retina_name))
-                .header("Accept", "application/json")
-                .header("Referer", "")
+        let request =
+            self.request(Method::POST, &format!("/rest/text/keywords?retina_name={}", retina_name))
                 .header("Content-Type", "text/plain;charset=UTF-8")
-                .body(text.to_string())
-                .send()
-                .await?;
+                .body(text.to_string());
 
-        Ok(
-            response
-                .json()
-                .await?
-        )
+        self.execute(request, true).await
     }
 
     pub async fn get_text_slices(
         &self,
         text: &str,
         params: Option<TextSliceRequest>,
-    ) -> Result<Vec<TextSlice>, Box<dyn Error>> {
+    ) -> Result<Vec<TextSlice>, Error> {
         let params = params.unwrap_or_default();
 
-        let response =
-            self.client
-                .post(
-                    format!(
-                        "{}/rest/text/slices?retina_name={}&start_index={}&max_results={}&get_fingerprint={}",
-                        &self.base_url,
-                        params.retina_name,
-                        params.start_index,
-                        params.max_results,
-                        params.get_fingerprint
-                    )
-                )
-                .header("Accept", "application/json")
-                .header("Referer", "")
+        let request =
+            self.request(Method::POST, "/rest/text/slices")
+                .query(&params)
                 .header("Content-Type", "application/json")
-                .body(text.to_string())
-                .send()
-                .await?;
+                .body(text.to_string());
 
-        Ok(
-            response
-                .json()
-                .await?
-        )
+        self.execute(request, true).await
     }
 
     pub async fn get_text_detect_language(
         &self,
         text: &str,
-    ) -> Result<LanguageResponse, Box<dyn Error>> {
-        let response =
-            self.client
-                .post(format!("{}/rest/text/detect_language", &self.base_url))
-                .header("Accept", "application/json")
-                .header("Referer", "")
+    ) -> Result<LanguageResponse, Error> {
+        let request =
+            self.request(Method::POST, "/rest/text/detect_language")
                 .header("Content-Type", "application/json")
-                .body(text.to_string())
-                .send()
-                .await?;
+                .body(text.to_string());
 
-        Ok(
-            response
-                .json()
-                .await?
-        )
+        self.execute(request, true).await
     }
 
     pub async fn create_category_filter(
@@ -136,7 +403,7 @@ impl Cortical {
         positive_examples: Vec<String>,
         negative_examples: Vec<String>,
         retina_name: Option<&str>,
-    ) -> Result<CreateCategoryFilterResponse, Box<dyn Error>> {
+    ) -> Result<CreateCategoryFilterResponse, Error> {
         let retina_name = retina_name.unwrap_or("en_general");
 
         let positive_examples = positive_examples
@@ -155,53 +422,44 @@ impl Cortical {
             negative_examples,
         };
 
-        Ok(
-            self.client
-                .post(
-                    format!(
-                        "{}/rest/classify/create_category_filter?retina_name={}&filter_name={}",
-                        &self.base_url,
-                        retina_name,
-                        "filter_name"
-                    )
-                )
-                .header("Accept", "application/json")
-                .header("Referer", "")
+        let request =
+            self.request(
+                Method::POST,
+                &format!(
+                    "/rest/classify/create_category_filter?retina_name={}&filter_name={}",
+                    retina_name,
+                    "filter_name"
+                ),
+            )
                 .header("Content-Type", "application/json")
-                .body(serde_json::to_string(&request)?)
-                .send()
-                .await?
-                .json()
-                .await?
-        )
+                .body(serde_json::to_string(&request).map_err(Error::Serialize)?);
+
+        self.execute(request, false).await
     }
 
     pub async fn get_compare(
         &self,
         (text1, text2): (&str, &str),
         retina_name: Option<&str>,
-    ) -> Result<CompareResponse, Box<dyn Error>> {
+    ) -> Result<CompareResponse, Error> {
+        self.compare(Expression::text(text1), Expression::text(text2), retina_name).await
+    }
+
+    /// Compares any two expressions, e.g. a stored [`Fingerprint`] against a fresh text.
+    pub async fn compare(
+        &self,
+        left: impl Into<Expression>,
+        right: impl Into<Expression>,
+        retina_name: Option<&str>,
+    ) -> Result<CompareResponse, Error> {
         let retina_name = retina_name.unwrap_or("en_general");
 
-        Ok(
-            self.client
-                .post(format!("{}/rest/compare?retina_name={}", &self.base_url, retina_name))
-                .header("Accept", "application/json")
-                .header("Referer", "")
+        let request =
+            self.request(Method::POST, &format!("/rest/compare?retina_name={}", retina_name))
                 .header("Content-Type", "application/json")
-                .body(
-                    serde_json::to_string(
-                        &vec![
-                            TextEnvelope::new(text1),
-                            TextEnvelope::new(text2),
-                        ]
-                    )?
-                )
-                .send()
-                .await?
-                .json()
-                .await?
-        )
+                .body(serde_json::to_string(&[left.into(), right.into()]).map_err(Error::Serialize)?);
+
+        self.execute(request, true).await
     }
 
     pub async fn get_terms(
@@ -211,7 +469,7 @@ impl Cortical {
         get_fingerpint: Option<bool>,
         start_index: Option<u32>,
         max_results: Option<u32>,
-    ) -> Result<GetTermsResponse, Box<dyn Error>> {
+    ) -> Result<GetTermsResponse, Error> {
         let retina_name = retina_name.unwrap_or("en_general");
 
         let query =
@@ -223,21 +481,12 @@ impl Cortical {
                 get_fingerprint: get_fingerpint.unwrap_or(false),
             };
 
-        let response =
-            self.client
-                .get(format!("{}/rest/terms", &self.base_url))
-                .header("Accept", "application/json")
-                .header("Referer", "")
+        let request =
+            self.request(Method::GET, "/rest/terms")
                 .header("Content-Type", "application/json")
-                .query(&query)
-                .send()
-                .await?;
+                .query(&query);
 
-        Ok(
-            response
-                .json()
-                .await?
-        )
+        self.execute(request, true).await
     }
 
     pub async fn get_terms_contexts(
@@ -247,7 +496,7 @@ impl Cortical {
         get_fingerpint: Option<bool>,
         start_index: Option<u32>,
         max_results: Option<u32>,
-    ) -> Result<GetTermsContextsResponse, Box<dyn Error>> {
+    ) -> Result<GetTermsContextsResponse, Error> {
         let retina_name = retina_name.unwrap_or("en_general");
 
         let query =
@@ -259,23 +508,15 @@ impl Cortical {
                 get_fingerprint: get_fingerpint.unwrap_or(false),
             };
 
-        let response =
-            self.client
-                .get(format!("{}/rest/terms/contexts", &self.base_url))
-                .header("Accept", "application/json")
-                .header("Referer", "")
+        let request =
+            self.request(Method::GET, "/rest/terms/contexts")
                 .header("Content-Type", "application/json")
-                .query(&query)
-                .send()
-                .await?;
+                .query(&query);
 
-        Ok(
-            response
-                .json()
-                .await?
-        )
+        self.execute(request, true).await
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn get_terms_similar_terms(
         &self,
         term: &str,
@@ -285,7 +526,7 @@ impl Cortical {
         get_fingerpint: Option<bool>,
         start_index: Option<u32>,
         max_results: Option<u32>,
-    ) -> Result<GetTermsResponse, Box<dyn Error>> {
+    ) -> Result<GetTermsResponse, Error> {
         let retina_name = retina_name.unwrap_or("en_general");
 
         let query =
@@ -299,20 +540,1056 @@ impl Cortical {
                 get_fingerprint: get_fingerpint.unwrap_or(false),
             };
 
-        let response =
-            self.client
-                .get(format!("{}/rest/terms/contexts", &self.base_url))
-                .header("Accept", "application/json")
-                .header("Referer", "")
+        let request =
+            self.request(Method::GET, "/rest/terms/similar_terms")
                 .header("Content-Type", "application/json")
-                .query(&query)
-                .send()
-                .await?;
+                .query(&query);
 
-        Ok(
-            response
-                .json()
-                .await?
-        )
+        self.execute(request, true).await
+    }
+
+    pub async fn get_expression(
+        &self,
+        expression: &Expression,
+        params: Option<GetExpressionRequest>,
+    ) -> Result<Fingerprint, Error> {
+        let params = params.unwrap_or_default();
+
+        let request =
+            self.request(Method::POST, "/rest/expressions")
+                .query(&params)
+                .header("Content-Type", "application/json")
+                .body(serde_json::to_string(expression).map_err(Error::Serialize)?);
+
+        self.execute(request, true).await
+    }
+
+    pub async fn get_expression_contexts(
+        &self,
+        expression: &Expression,
+        params: Option<GetExpressionContextsRequest>,
+    ) -> Result<GetTermsContextsResponse, Error> {
+        let params = params.unwrap_or_default();
+
+        let request =
+            self.request(Method::POST, "/rest/expressions/contexts")
+                .query(&params)
+                .header("Content-Type", "application/json")
+                .body(serde_json::to_string(expression).map_err(Error::Serialize)?);
+
+        self.execute(request, true).await
+    }
+
+    pub async fn get_expression_similar_terms(
+        &self,
+        expression: &Expression,
+        params: Option<GetExpressionSimilarTermsRequest>,
+    ) -> Result<GetTermsResponse, Error> {
+        let params = params.unwrap_or_default();
+
+        let request =
+            self.request(Method::POST, "/rest/expressions/similar_terms")
+                .query(&params)
+                .header("Content-Type", "application/json")
+                .body(serde_json::to_string(expression).map_err(Error::Serialize)?);
+
+        self.execute(request, true).await
+    }
+
+    pub async fn get_expressions_bulk(
+        &self,
+        expressions: &[Expression],
+        params: Option<GetExpressionRequest>,
+    ) -> Result<Vec<Fingerprint>, Error> {
+        let params = params.unwrap_or_default();
+
+        self.execute_bulk(
+            || self.request(Method::POST, "/rest/expressions/bulk").query(&params),
+            expressions,
+        ).await
+    }
+
+    pub async fn get_text_analysis_bulk(
+        &self,
+        texts: &[&str],
+        retina_name: Option<&str>,
+    ) -> Result<Vec<Fingerprint>, Error> {
+        let retina_name = retina_name.unwrap_or("en_general");
+
+        let texts =
+            texts.iter()
+                .map(|text| TextEnvelope::new(text))
+                .collect::<Vec<_>>();
+
+        self.execute_bulk(
+            || self.request(Method::POST, &format!("/rest/text/bulk?retina_name={}", retina_name)),
+            &texts,
+        ).await
+    }
+
+    pub async fn get_compare_bulk(
+        &self,
+        pairs: &[(&str, &str)],
+        retina_name: Option<&str>,
+    ) -> Result<Vec<CompareResponse>, Error> {
+        let pairs =
+            pairs.iter()
+                .map(|(text1, text2)| (Expression::text(text1), Expression::text(text2)))
+                .collect::<Vec<_>>();
+
+        self.compare_bulk(&pairs, retina_name).await
+    }
+
+    pub async fn compare_bulk(
+        &self,
+        pairs: &[(Expression, Expression)],
+        retina_name: Option<&str>,
+    ) -> Result<Vec<CompareResponse>, Error> {
+        let retina_name = retina_name.unwrap_or("en_general");
+
+        self.execute_bulk(
+            || self.request(Method::POST, &format!("/rest/compare/bulk?retina_name={}", retina_name)),
+            pairs,
+        ).await
+    }
+
+    /// Renders the fingerprint of `expression` as a PNG on the server.
+    pub async fn get_image(
+        &self,
+        expression: &Expression,
+        params: Option<GetImageRequest>,
+    ) -> Result<Vec<u8>, Error> {
+        let params = params.unwrap_or_default();
+
+        let request =
+            self.image_request("/rest/image", &params)
+                .body(serde_json::to_string(expression).map_err(Error::Serialize)?);
+
+        self.execute_image(request, &params).await
+    }
+
+    /// Renders the fingerprints of two expressions on top of each other as a PNG.
+    pub async fn get_image_compare(
+        &self,
+        left: &Expression,
+        right: &Expression,
+        params: Option<GetImageRequest>,
+    ) -> Result<Vec<u8>, Error> {
+        let params = params.unwrap_or_default();
+
+        let request =
+            self.image_request("/rest/image/compare", &params)
+                .body(serde_json::to_string(&[left, right]).map_err(Error::Serialize)?);
+
+        self.execute_image(request, &params).await
+    }
+
+    /// Renders one base64 encoded PNG per expression, optionally along with its fingerprint.
+    pub async fn get_images_bulk(
+        &self,
+        expressions: &[Expression],
+        params: Option<GetImageRequest>,
+    ) -> Result<Vec<ImageData>, Error> {
+        let params = params.unwrap_or_default();
+
+        self.execute_bulk(
+            || self.request(Method::POST, "/rest/image/bulk").query(&params),
+            expressions,
+        ).await
+    }
+
+    #[cfg(feature = "image")]
+    pub async fn get_dynamic_image(
+        &self,
+        expression: &Expression,
+        params: Option<GetImageRequest>,
+    ) -> Result<image::DynamicImage, Error> {
+        let png = self.get_image(expression, params).await?;
+
+        Ok(image::load_from_memory(&png)?)
+    }
+
+    #[cfg(feature = "image")]
+    pub async fn get_dynamic_image_compare(
+        &self,
+        left: &Expression,
+        right: &Expression,
+        params: Option<GetImageRequest>,
+    ) -> Result<image::DynamicImage, Error> {
+        let png = self.get_image_compare(left, right, params).await?;
+
+        Ok(image::load_from_memory(&png)?)
+    }
+
+    /// A request that accepts a PNG instead of JSON, unless a base64 encoding was asked for.
+    fn image_request(&self, path: &str, params: &GetImageRequest) -> RequestBuilder {
+        let accept =
+            match params.image_encoding {
+                Some(ImageEncoding::Base64Png) => "text/plain",
+                None => "image/png",
+            };
+
+        let mut headers = HeaderMap::new();
+        headers.insert("Accept", HeaderValue::from_static(accept));
+        headers.insert("Content-Type", HeaderValue::from_static("application/json"));
+
+        self.request(Method::POST, path)
+            .query(params)
+            .headers(headers)
+    }
+
+    async fn execute_image(&self, request: RequestBuilder, params: &GetImageRequest) -> Result<Vec<u8>, Error> {
+        let body = self.execute_raw(request, true).await?;
+
+        match params.image_encoding {
+            Some(ImageEncoding::Base64Png) => crate::decode_base64(&String::from_utf8_lossy(&body)),
+            None => Ok(body),
+        }
+    }
+}
+
+async fn read_body(response: reqwest::Response) -> Result<Vec<u8>, Error> {
+    let status = response.status();
+
+    if !status.is_success() {
+        let endpoint = response.url().path().to_string();
+
+        let request_id =
+            REQUEST_ID_HEADERS
+                .iter()
+                .find_map(|name| response.headers().get(*name))
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string());
+
+        let raw = response.text().await?;
+
+        return Err(Error::from_api(ApiError::new(status.as_u16(), &endpoint, request_id, raw)));
+    }
+
+    Ok(
+        response
+            .bytes()
+            .await?
+            .to_vec()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::json;
+    use wiremock::matchers::{body_json, body_string, header, method, path, query_param, query_param_is_missing};
+    use wiremock::{Mock, MockBuilder, MockServer, Request, ResponseTemplate};
+
+    use crate::expression::{Expression, GetExpressionContextsRequest, GetExpressionRequest, GetExpressionSimilarTermsRequest};
+    use crate::retry::RetryPolicy;
+    use crate::{Error, Fingerprint, GetImageRequest, ImageEncoding, PlotShape, PosType, TextSliceRequest};
+
+    use super::{api_key_header, Cortical};
+
+    fn bulk_client(server: &MockServer, bulk_batch_size: usize) -> Cortical {
+        Cortical::builder()
+            .with_base_url(&server.uri())
+            .with_api_key("test")
+            .with_bulk_batch_size(bulk_batch_size)
+            .with_bulk_concurrency(3)
+            .build()
+            .unwrap()
+    }
+
+    fn client(server: &MockServer, retry: RetryPolicy) -> Cortical {
+        Cortical::builder()
+            .with_base_url(&server.uri())
+            .with_api_key("test")
+            .with_retry_policy(retry)
+            .build()
+            .unwrap()
+    }
+
+    /// Matches a request to `endpoint` carrying the default headers every call sends.
+    fn expect_call(verb: &str, endpoint: &str) -> MockBuilder {
+        Mock::given(method(verb))
+            .and(path(endpoint))
+            .and(header("accept", "application/json"))
+            .and(header("api-key", "test"))
+    }
+
+    fn reply(body: serde_json::Value) -> ResponseTemplate {
+        ResponseTemplate::new(200).set_body_json(body)
+    }
+
+    fn fast_retry() -> RetryPolicy {
+        RetryPolicy::new()
+            .with_max_attempts(3)
+            .with_initial_backoff(Duration::from_millis(1))
+            .with_jitter(false)
+    }
+
+    #[test]
+    fn builder_headers() {
+        let cortical =
+            Cortical::builder()
+                .with_base_url("http://localhost:8080/")
+                .with_api_key("secret")
+                .with_bearer_token("token")
+                .with_user_agent("cortical-io-tests")
+                .with_header("Referer", "https://example.com")
+                .build()
+                .unwrap();
+
+        assert_eq!(cortical.base_url, "http://localhost:8080");
+        assert_eq!(cortical.headers["api-key"], "secret");
+        assert_eq!(cortical.headers["authorization"], "Bearer token");
+        assert_eq!(cortical.headers["user-agent"], "cortical-io-tests");
+        assert_eq!(cortical.headers["referer"], "https://example.com");
+        assert_eq!(cortical.headers["accept"], "application/json");
+    }
+
+    #[test]
+    fn builder_rejects_invalid_headers() {
+        assert!(Cortical::builder().with_header("bad header", "x").build().is_err());
+        assert!(Cortical::builder().with_api_key("line\nbreak").build().is_err());
+    }
+
+    #[test]
+    fn skips_invalid_api_key_from_env_only_when_asked() {
+        let env = || Some("line\nbreak".to_string());
+
+        assert_eq!(api_key_header(None, env(), true).unwrap(), None);
+        assert!(matches!(api_key_header(None, env(), false), Err(Error::Config(_))));
+        assert!(api_key_header(Some("line\nbreak".to_string()), None, true).is_err());
+        assert_eq!(api_key_header(Some("secret".to_string()), env(), false).unwrap().unwrap(), "secret");
+    }
+
+    #[tokio::test]
+    async fn retries_unavailable_responses() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/rest/retinas"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(2)
+            .expect(2)
+            .with_priority(1)
+            .mount(&server)
+            .await;
+
+        Mock::given(method("GET"))
+            .and(path("/rest/retinas"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("[]"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let retinas = client(&server, fast_retry()).get_retinas().await.unwrap();
+
+        assert!(retinas.is_empty());
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_attempts() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/rest/text"))
+            .respond_with(
+                ResponseTemplate::new(429)
+                    .insert_header("Retry-After", "0")
+                    .set_body_string(r#"{"message":"Too many requests"}"#)
+            )
+            .expect(3)
+            .mount(&server)
+            .await;
+
+        let err =
+            client(&server, fast_retry())
+                .get_text_analysis("text", None)
+                .await
+                .unwrap_err();
+
+        assert!(matches!(err, Error::Quota(_)));
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_non_idempotent_calls() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/rest/classify/create_category_filter"))
+            .respond_with(ResponseTemplate::new(503))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let err =
+            client(&server, fast_retry())
+                .create_category_filter(vec!["a".to_string()], vec![], None)
+                .await
+                .unwrap_err();
+
+        assert_eq!(err.status(), Some(503));
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_client_errors() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/rest/retinas"))
+            .respond_with(ResponseTemplate::new(400))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let err = client(&server, fast_retry()).get_retinas().await.unwrap_err();
+
+        assert_eq!(err.status(), Some(400));
+    }
+
+    #[tokio::test]
+    async fn get_retinas_request() {
+        let server = MockServer::start().await;
+
+        expect_call("GET", "/rest/retinas")
+            .respond_with(reply(json!([{
+                "retinaName": "en_general",
+                "description": "An English language retina",
+                "numberOfColumns": 128,
+                "numberOfRows": 128,
+                "numberOfTermsInRetina": 854523
+            }])))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let retinas = client(&server, RetryPolicy::none()).get_retinas().await.unwrap();
+
+        assert_eq!(retinas[0].retina_name, "en_general");
+        assert_eq!(retinas[0].number_of_rows, 128);
+    }
+
+    #[tokio::test]
+    async fn get_text_analysis_request() {
+        let server = MockServer::start().await;
+
+        expect_call("POST", "/rest/text")
+            .and(query_param("retina_name", "en_associative"))
+            .and(header("content-type", "application/json"))
+            .and(body_string("a text"))
+            .respond_with(reply(json!([{ "positions": [1, 2, 3] }])))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let fingerprints =
+            client(&server, RetryPolicy::none())
+                .get_text_analysis("a text", Some("en_associative"))
+                .await
+                .unwrap();
+
+        assert_eq!(fingerprints[0].positions, vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn get_text_keywords_request() {
+        let server = MockServer::start().await;
+
+        expect_call("POST", "/rest/text/keywords")
+            .and(query_param("retina_name", "en_general"))
+            .and(header("content-type", "text/plain;charset=UTF-8"))
+            .and(body_string("a text about apples"))
+            .respond_with(reply(json!(["apples", "text"])))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let keywords =
+            client(&server, RetryPolicy::none())
+                .get_text_keywords("a text about apples", None)
+                .await
+                .unwrap();
+
+        assert_eq!(keywords, vec!["apples", "text"]);
+    }
+
+    #[tokio::test]
+    async fn get_text_slices_request() {
+        let server = MockServer::start().await;
+
+        expect_call("POST", "/rest/text/slices")
+            .and(query_param("retina_name", "en_general"))
+            .and(query_param("start_index", "5"))
+            .and(query_param("max_results", "20"))
+            .and(query_param("get_fingerprint", "true"))
+            .and(body_string("first. second."))
+            .respond_with(reply(json!([
+                { "text": "first.", "fingerprint": { "positions": [4, 5] } },
+                { "text": "second." }
+            ])))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let slices =
+            client(&server, RetryPolicy::none())
+                .get_text_slices(
+                    "first. second.",
+                    Some(
+                        TextSliceRequest::new()
+                            .with_start_index(5)
+                            .with_max_results(20)
+                            .with_get_fingerprint(true)
+                    ),
+                )
+                .await
+                .unwrap();
+
+        assert_eq!(slices.len(), 2);
+        assert_eq!(slices[0].fingerprint.as_ref().unwrap().positions, vec![4, 5]);
+        assert_eq!(slices[1].fingerprint, None);
+    }
+
+    #[tokio::test]
+    async fn get_text_detect_language_request() {
+        let server = MockServer::start().await;
+
+        expect_call("POST", "/rest/text/detect_language")
+            .and(body_string("Das ist ein Text"))
+            .respond_with(reply(json!({
+                "language": "German",
+                "iso_tag": "de",
+                "wiki_url": "http://en.wikipedia.org/wiki/German_language"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let language =
+            client(&server, RetryPolicy::none())
+                .get_text_detect_language("Das ist ein Text")
+                .await
+                .unwrap();
+
+        assert_eq!(language.iso_tag.as_deref(), Some("de"));
+    }
+
+    #[tokio::test]
+    async fn create_category_filter_request() {
+        let server = MockServer::start().await;
+
+        expect_call("POST", "/rest/classify/create_category_filter")
+            .and(query_param("retina_name", "en_general"))
+            .and(query_param("filter_name", "filter_name"))
+            .and(header("content-type", "application/json"))
+            .and(body_json(json!({
+                "categoryName": null,
+                "positiveExamples": [{ "text": "apples" }],
+                "negativeExamples": [{ "text": "cars" }]
+            })))
+            .respond_with(reply(json!({ "categoryName": "filter_name", "positions": [7, 8] })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let filter =
+            client(&server, RetryPolicy::none())
+                .create_category_filter(vec!["apples".to_string()], vec!["cars".to_string()], None)
+                .await
+                .unwrap();
+
+        assert_eq!(filter.positions, vec![7, 8]);
+    }
+
+    #[tokio::test]
+    async fn get_compare_request() {
+        let server = MockServer::start().await;
+
+        expect_call("POST", "/rest/compare")
+            .and(query_param("retina_name", "en_general"))
+            .and(header("content-type", "application/json"))
+            .and(body_json(json!([{ "text": "left" }, { "text": "right" }])))
+            .respond_with(reply(json!({
+                "sizeLeft": 10,
+                "sizeRight": 12,
+                "weightedScoring": 0.5,
+                "euclideanDistance": 0.7,
+                "jaccardDistance": 0.8,
+                "overlappingAll": 4,
+                "overlappingLeftRight": 0.4,
+                "overlappingRightLeft": 0.33,
+                "cosineSimilarity": 0.36
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let compare =
+            client(&server, RetryPolicy::none())
+                .get_compare(("left", "right"), None)
+                .await
+                .unwrap();
+
+        assert_eq!(compare.overlapping_all, 4);
+        assert_eq!(compare.size_right, 12);
+    }
+
+    #[tokio::test]
+    async fn get_terms_request() {
+        let server = MockServer::start().await;
+
+        expect_call("GET", "/rest/terms")
+            .and(query_param("retina_name", "en_general"))
+            .and(query_param("term", "apple"))
+            .and(query_param("start_index", "0"))
+            .and(query_param("max_results", "5"))
+            .and(query_param("get_fingerprint", "true"))
+            .respond_with(reply(json!([{ "term": "apple", "df": 0.0001, "score": 0.0 }])))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let terms =
+            client(&server, RetryPolicy::none())
+                .get_terms(None, Some("apple"), Some(true), Some(0), Some(5))
+                .await
+                .unwrap();
+
+        assert_eq!(terms[0].term.as_deref(), Some("apple"));
+    }
+
+    #[tokio::test]
+    async fn get_terms_omits_unset_parameters() {
+        let server = MockServer::start().await;
+
+        expect_call("GET", "/rest/terms")
+            .and(query_param("retina_name", "en_general"))
+            .and(query_param_is_missing("term"))
+            .and(query_param_is_missing("start_index"))
+            .and(query_param_is_missing("max_results"))
+            .and(query_param("get_fingerprint", "false"))
+            .respond_with(reply(json!([])))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        client(&server, RetryPolicy::none())
+            .get_terms(None, None, None, None, None)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn get_terms_contexts_request() {
+        let server = MockServer::start().await;
+
+        expect_call("GET", "/rest/terms/contexts")
+            .and(query_param("retina_name", "en_general"))
+            .and(query_param("term", "apple"))
+            .and(query_param("max_results", "3"))
+            .and(query_param("get_fingerprint", "false"))
+            .respond_with(reply(json!([{ "contextLabel": "fruit", "contextId": 1 }])))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let contexts =
+            client(&server, RetryPolicy::none())
+                .get_terms_contexts("apple", None, None, None, Some(3))
+                .await
+                .unwrap();
+
+        assert_eq!(contexts[0].context_label.as_deref(), Some("fruit"));
+        assert_eq!(contexts[0].context_id, Some(1));
+    }
+
+    #[tokio::test]
+    async fn get_terms_similar_terms_request() {
+        let server = MockServer::start().await;
+
+        expect_call("GET", "/rest/terms/similar_terms")
+            .and(query_param("retina_name", "en_general"))
+            .and(query_param("term", "apple"))
+            .and(query_param("context_id", "2"))
+            .and(query_param("pos_type", "NOUN"))
+            .and(query_param("max_results", "10"))
+            .and(query_param("get_fingerprint", "false"))
+            .respond_with(reply(json!([{ "term": "pear" }, { "term": "banana" }])))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let terms =
+            client(&server, RetryPolicy::none())
+                .get_terms_similar_terms("apple", None, Some("2"), Some(PosType::Noun), None, None, Some(10))
+                .await
+                .unwrap();
+
+        assert_eq!(terms[1].term.as_deref(), Some("banana"));
+    }
+
+    fn jaguar_not_car() -> Expression {
+        Expression::sub([Expression::term("jaguar"), Expression::term("car")])
+    }
+
+    #[tokio::test]
+    async fn get_expression_request() {
+        let server = MockServer::start().await;
+
+        expect_call("POST", "/rest/expressions")
+            .and(query_param("retina_name", "en_general"))
+            .and(query_param("sparsity", "0.02"))
+            .and(header("content-type", "application/json"))
+            .and(body_json(json!({ "sub": [{ "term": "jaguar" }, { "term": "car" }] })))
+            .respond_with(reply(json!({ "positions": [3, 4] })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let fingerprint =
+            client(&server, RetryPolicy::none())
+                .get_expression(&jaguar_not_car(), Some(GetExpressionRequest::new().with_sparsity(0.02)))
+                .await
+                .unwrap();
+
+        assert_eq!(fingerprint.positions, vec![3, 4]);
+    }
+
+    #[tokio::test]
+    async fn get_expression_contexts_request() {
+        let server = MockServer::start().await;
+
+        expect_call("POST", "/rest/expressions/contexts")
+            .and(query_param("retina_name", "en_associative"))
+            .and(query_param("max_results", "2"))
+            .and(query_param("get_fingerprint", "true"))
+            .and(query_param_is_missing("sparsity"))
+            .and(body_json(json!({ "sub": [{ "term": "jaguar" }, { "term": "car" }] })))
+            .respond_with(reply(json!([{ "contextLabel": "cat", "contextId": 0 }])))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let contexts =
+            client(&server, RetryPolicy::none())
+                .get_expression_contexts(
+                    &jaguar_not_car(),
+                    Some(
+                        GetExpressionContextsRequest::new()
+                            .with_retina_name("en_associative")
+                            .with_max_results(2)
+                            .with_get_fingerprint(true)
+                    ),
+                )
+                .await
+                .unwrap();
+
+        assert_eq!(contexts[0].context_label.as_deref(), Some("cat"));
+    }
+
+    #[tokio::test]
+    async fn get_expression_similar_terms_request() {
+        let server = MockServer::start().await;
+
+        expect_call("POST", "/rest/expressions/similar_terms")
+            .and(query_param("retina_name", "en_general"))
+            .and(query_param("context_id", "0"))
+            .and(query_param("pos_type", "NOUN"))
+            .and(body_json(json!({ "sub": [{ "term": "jaguar" }, { "term": "car" }] })))
+            .respond_with(reply(json!([{ "term": "leopard" }])))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let terms =
+            client(&server, RetryPolicy::none())
+                .get_expression_similar_terms(
+                    &jaguar_not_car(),
+                    Some(
+                        GetExpressionSimilarTermsRequest::new()
+                            .with_context_id(0)
+                            .with_pos_type(PosType::Noun)
+                    ),
+                )
+                .await
+                .unwrap();
+
+        assert_eq!(terms[0].term.as_deref(), Some("leopard"));
+    }
+
+    #[tokio::test]
+    async fn get_expressions_bulk_request() {
+        let server = MockServer::start().await;
+
+        expect_call("POST", "/rest/expressions/bulk")
+            .and(query_param("retina_name", "en_general"))
+            .and(body_json(json!([{ "term": "jaguar" }, { "positions": [1, 2] }])))
+            .respond_with(reply(json!([{ "positions": [9] }, { "positions": [1, 2] }])))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let fingerprints =
+            client(&server, RetryPolicy::none())
+                .get_expressions_bulk(&[Expression::term("jaguar"), Expression::positions(&[1, 2])], None)
+                .await
+                .unwrap();
+
+        assert_eq!(fingerprints.len(), 2);
+        assert_eq!(fingerprints[1].positions, vec![1, 2]);
+    }
+
+    /// Answers each text with a fingerprint holding its length, delaying the first batches the
+    /// most so that they complete last.
+    fn echo_text_lengths(request: &Request) -> ResponseTemplate {
+        let texts: Vec<serde_json::Value> = request.body_json().unwrap();
+
+        let fingerprints =
+            texts.iter()
+                .map(|text| json!({ "positions": [text["text"].as_str().unwrap().len()] }))
+                .collect::<Vec<_>>();
+
+        let delay = 60 - 10 * texts[0]["text"].as_str().unwrap().len() as u64;
+
+        reply(json!(fingerprints)).set_delay(Duration::from_millis(delay))
+    }
+
+    #[tokio::test]
+    async fn get_text_analysis_bulk_keeps_input_order() {
+        let server = MockServer::start().await;
+
+        expect_call("POST", "/rest/text/bulk")
+            .and(query_param("retina_name", "en_general"))
+            .and(header("content-type", "application/json"))
+            .respond_with(echo_text_lengths)
+            .expect(3)
+            .mount(&server)
+            .await;
+
+        let texts = ["a", "bb", "ccc", "dddd", "eeeee"];
+
+        let fingerprints =
+            bulk_client(&server, 2)
+                .get_text_analysis_bulk(&texts, None)
+                .await
+                .unwrap();
+
+        assert_eq!(
+            fingerprints.iter().map(|fingerprint| fingerprint.positions[0]).collect::<Vec<_>>(),
+            vec![1, 2, 3, 4, 5]
+        );
+    }
+
+    #[tokio::test]
+    async fn get_compare_bulk_request() {
+        let server = MockServer::start().await;
+
+        let response = json!({
+            "sizeLeft": 1,
+            "sizeRight": 1,
+            "weightedScoring": 1.0,
+            "euclideanDistance": 0.0,
+            "jaccardDistance": 0.0,
+            "overlappingAll": 1,
+            "overlappingLeftRight": 1.0,
+            "overlappingRightLeft": 1.0,
+            "cosineSimilarity": 1.0
+        });
+
+        expect_call("POST", "/rest/compare/bulk")
+            .and(query_param("retina_name", "en_general"))
+            .and(body_json(json!([[{ "text": "a" }, { "text": "b" }], [{ "text": "c" }, { "text": "d" }]])))
+            .respond_with(reply(json!([response, response])))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let results =
+            bulk_client(&server, 10)
+                .get_compare_bulk(&[("a", "b"), ("c", "d")], None)
+                .await
+                .unwrap();
+
+        assert_eq!(results.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn bulk_rejects_short_responses() {
+        let server = MockServer::start().await;
+
+        expect_call("POST", "/rest/expressions/bulk")
+            .respond_with(reply(json!([{ "positions": [1] }])))
+            .mount(&server)
+            .await;
+
+        let err =
+            bulk_client(&server, 10)
+                .get_expressions_bulk(&[Expression::term("a"), Expression::term("b")], None)
+                .await
+                .unwrap_err();
+
+        assert!(matches!(err, Error::BulkLength { expected: 2, actual: 1 }));
+    }
+
+    #[tokio::test]
+    async fn compare_fingerprint_with_text() {
+        let server = MockServer::start().await;
+
+        expect_call("POST", "/rest/compare")
+            .and(query_param("retina_name", "en_general"))
+            .and(body_json(json!([{ "positions": [1, 5, 9] }, { "text": "fresh text" }])))
+            .respond_with(reply(serde_json::from_str(include_str!("../fixtures/compare.json")).unwrap()))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let stored = Fingerprint { positions: vec![1, 5, 9] };
+
+        let compare =
+            client(&server, RetryPolicy::none())
+                .compare(&stored, Expression::text("fresh text"), None)
+                .await
+                .unwrap();
+
+        assert_eq!(compare.overlapping_all, 215);
     }
-}
\ No newline at end of file
+
+    #[cfg(feature = "image")]
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut png = std::io::Cursor::new(Vec::new());
+
+        image::DynamicImage::new_rgb8(width, height)
+            .write_to(&mut png, image::ImageFormat::Png)
+            .unwrap();
+
+        png.into_inner()
+    }
+
+    #[tokio::test]
+    async fn get_image_request() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/rest/image"))
+            .and(header("accept", "image/png"))
+            .and(header("api-key", "test"))
+            .and(query_param("retina_name", "en_general"))
+            .and(query_param("plot_shape", "square"))
+            .and(query_param("image_scalar", "4"))
+            .and(query_param_is_missing("image_encoding"))
+            .and(body_json(json!({ "term": "jaguar" })))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(b"\x89PNG".to_vec(), "image/png"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let params =
+            GetImageRequest::new()
+                .with_plot_shape(PlotShape::Square)
+                .with_image_scalar(4);
+
+        let png =
+            client(&server, RetryPolicy::none())
+                .get_image(&Expression::term("jaguar"), Some(params))
+                .await
+                .unwrap();
+
+        assert_eq!(png, b"\x89PNG");
+    }
+
+    #[tokio::test]
+    async fn get_image_decodes_base64() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/rest/image/compare"))
+            .and(query_param("image_encoding", "base64/png"))
+            .and(body_json(json!([{ "term": "jaguar" }, { "term": "car" }])))
+            .respond_with(ResponseTemplate::new(200).set_body_string("iVBORw==\n"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let png =
+            client(&server, RetryPolicy::none())
+                .get_image_compare(
+                    &Expression::term("jaguar"),
+                    &Expression::term("car"),
+                    Some(GetImageRequest::new().with_image_encoding(ImageEncoding::Base64Png)),
+                )
+                .await
+                .unwrap();
+
+        assert_eq!(png, b"\x89PNG");
+    }
+
+    #[tokio::test]
+    async fn get_images_bulk_request() {
+        let server = MockServer::start().await;
+
+        expect_call("POST", "/rest/image/bulk")
+            .and(query_param("get_fingerprint", "true"))
+            .and(body_json(json!([{ "term": "jaguar" }, { "term": "car" }])))
+            .respond_with(reply(json!([
+                { "image_data": "iVBORw==", "fingerprint": { "positions": [1, 2] } },
+                { "image_data": "iVBORw==" }
+            ])))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let images =
+            client(&server, RetryPolicy::none())
+                .get_images_bulk(
+                    &[Expression::term("jaguar"), Expression::term("car")],
+                    Some(GetImageRequest::new().with_get_fingerprint(true)),
+                )
+                .await
+                .unwrap();
+
+        assert_eq!(images[0].png().unwrap(), b"\x89PNG");
+        assert_eq!(images[0].fingerprint, Some(Fingerprint { positions: vec![1, 2] }));
+        assert_eq!(images[1].fingerprint, None);
+    }
+
+    #[cfg(feature = "image")]
+    #[tokio::test]
+    async fn get_dynamic_image_request() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/rest/image"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(png(16, 8), "image/png"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let image =
+            client(&server, RetryPolicy::none())
+                .get_dynamic_image(&Expression::term("jaguar"), None)
+                .await
+                .unwrap();
+
+        assert_eq!((image.width(), image.height()), (16, 8));
+    }
+
+    #[cfg(feature = "image")]
+    #[tokio::test]
+    async fn get_dynamic_image_rejects_garbage() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/rest/image"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(b"not a png".to_vec(), "image/png"))
+            .mount(&server)
+            .await;
+
+        let err =
+            client(&server, RetryPolicy::none())
+                .get_dynamic_image(&Expression::term("jaguar"), None)
+                .await
+                .unwrap_err();
+
+        assert!(matches!(err, Error::Image(_)));
+    }
+}
@@ -0,0 +1,645 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::metric::MetricKind;
+use crate::minhash::mix;
+use crate::pairwise::{pairwise_bits, SimilarityMatrix};
+use crate::{BitFingerprint, Error, Fingerprint, SimilarityMetric};
+
+/// Options of [`k_medoids`].
+///
+/// Without a `sample_size`, or with one of at least the number of fingerprints, PAM runs on the
+/// full similarity matrix. Otherwise CLARA runs PAM on `samples` random samples of that size and
+/// keeps the medoids that fit the whole corpus best, so only `sample_size^2` scores are held in
+/// memory. Sampling is seeded and therefore reproducible.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KMedoids {
+    pub k: usize,
+    pub max_iterations: usize,
+    pub sample_size: Option<usize>,
+    pub samples: usize,
+    pub seed: u64,
+}
+
+impl KMedoids {
+    pub fn new(k: usize) -> Self {
+        Self {
+            k,
+            max_iterations: 100,
+            sample_size: None,
+            samples: 5,
+            seed: 0,
+        }
+    }
+
+    /// The maximum number of PAM swaps.
+    pub fn with_max_iterations(mut self, max_iterations: usize) -> Self {
+        self.max_iterations = max_iterations;
+        self
+    }
+
+    /// Switches to CLARA with samples of `sample_size` fingerprints.
+    pub fn with_sample_size(mut self, sample_size: usize) -> Self {
+        self.sample_size = Some(sample_size);
+        self
+    }
+
+    pub fn with_samples(mut self, samples: usize) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+}
+
+/// A partition of fingerprints around medoids.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Clustering {
+    /// The cluster of every fingerprint, an index into `medoids`.
+    pub assignments: Vec<usize>,
+    /// The fingerprint at the centre of every cluster, in ascending order.
+    pub medoids: Vec<usize>,
+    /// The sum of the scores of every fingerprint against its medoid.
+    pub score: f64,
+}
+
+impl Clustering {
+    /// The fingerprints of every cluster.
+    pub fn clusters(&self) -> Vec<Vec<usize>> {
+        let mut clusters = vec![Vec::new(); self.medoids.len()];
+
+        for (i, cluster) in self.assignments.iter().enumerate() {
+            clusters[*cluster].push(i);
+        }
+
+        clusters
+    }
+}
+
+/// How the score between two clusters is derived from the scores of their members.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Linkage {
+    /// The best pair.
+    #[serde(rename = "single")]
+    Single,
+    /// The worst pair.
+    #[serde(rename = "complete")]
+    Complete,
+    /// The mean over all pairs.
+    #[serde(rename = "average")]
+    Average,
+}
+
+/// One step of agglomerative clustering.
+///
+/// Ids below the number of fingerprints are fingerprints; the cluster created by merge `i` has
+/// id `len + i`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Merge {
+    pub left: usize,
+    pub right: usize,
+    /// The linkage score of the two clusters.
+    pub score: f64,
+    /// The number of fingerprints in the merged cluster.
+    pub size: usize,
+}
+
+/// The merge tree of agglomerative clustering, merges ordered best score first.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Dendrogram {
+    pub len: usize,
+    pub kind: MetricKind,
+    pub linkage: Linkage,
+    pub merges: Vec<Merge>,
+}
+
+impl Dendrogram {
+    /// Flat clusters from stopping once `k` clusters are left. Clusters are numbered in order of
+    /// their first fingerprint.
+    pub fn cut(&self, k: usize) -> Vec<usize> {
+        self.assignments(self.len.saturating_sub(k.max(1)))
+    }
+
+    /// Flat clusters from applying every merge whose score is at least as good as `score`.
+    pub fn cut_at(&self, score: f64) -> Vec<usize> {
+        let merges =
+            self.merges
+                .iter()
+                .take_while(|merge| self.kind.rank(merge.score, score).is_le())
+                .count();
+
+        self.assignments(merges)
+    }
+
+    fn assignments(&self, merges: usize) -> Vec<usize> {
+        let mut parent = (0..self.len).collect::<Vec<_>>();
+
+        // a fingerprint of every cluster
+        let mut leaves = (0..self.len).collect::<Vec<_>>();
+
+        for merge in &self.merges[..merges.min(self.merges.len())] {
+            let (left, right) = (find(&mut parent, leaves[merge.left]), find(&mut parent, leaves[merge.right]));
+
+            parent[left] = right;
+            leaves.push(right);
+        }
+
+        let mut labels = HashMap::new();
+
+        (0..self.len)
+            .map(|i| {
+                let root = find(&mut parent, i);
+                let next = labels.len();
+
+                *labels.entry(root).or_insert(next)
+            })
+            .collect()
+    }
+}
+
+fn find(parent: &mut [usize], mut i: usize) -> usize {
+    while parent[i] != i {
+        parent[i] = parent[parent[i]];
+        i = parent[i];
+    }
+
+    i
+}
+
+/// Clusters fingerprints of the default 128x128 retina around `k` medoids.
+pub fn k_medoids(fingerprints: &[Fingerprint], options: KMedoids, metric: &dyn SimilarityMetric) -> Result<Clustering, Error> {
+    let bits =
+        fingerprints
+            .iter()
+            .map(BitFingerprint::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+
+    check_k(options.k, bits.len())?;
+
+    match options.sample_size {
+        Some(sample_size) if sample_size.max(options.k) < bits.len() => Ok(clara(&bits, options, sample_size.max(options.k), metric)),
+        _ => k_medoids_matrix(&pairwise_bits(&bits, metric), options),
+    }
+}
+
+/// Runs PAM on precomputed scores, ignoring the CLARA options.
+pub fn k_medoids_matrix(matrix: &SimilarityMatrix, options: KMedoids) -> Result<Clustering, Error> {
+    check_k(options.k, matrix.len())?;
+
+    let (len, kind) = (matrix.len(), matrix.kind());
+    let distances = distances(matrix);
+
+    let medoids = pam(&distances, len, options.k, options.max_iterations);
+
+    Ok(clustering(len, medoids, kind, |i, j| distances[i * len + j]).0)
+}
+
+fn check_k(k: usize, len: usize) -> Result<(), Error> {
+    if k == 0 || k > len {
+        return Err(Error::Config(format!("cannot split {} fingerprints into {} clusters", len, k)));
+    }
+
+    Ok(())
+}
+
+fn clara(fingerprints: &[BitFingerprint], options: KMedoids, sample_size: usize, metric: &dyn SimilarityMetric) -> Clustering {
+    let (len, kind) = (fingerprints.len(), metric.kind());
+    let distance = |i: usize, j: usize| dissimilarity(kind, metric.score(&fingerprints[i], &fingerprints[j]));
+
+    let mut state = options.seed;
+    let mut best: Option<(Clustering, f64)> = None;
+
+    for _ in 0..options.samples.max(1) {
+        let mut order = (0..len).collect::<Vec<_>>();
+
+        for i in (1..len).rev() {
+            state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            order.swap(i, (mix(state) % (i as u64 + 1)) as usize);
+        }
+
+        // every sample after the first starts from the best medoids so far
+        let mut sample = best.as_ref().map(|(clustering, _)| clustering.medoids.clone()).unwrap_or_default();
+
+        for i in order {
+            if sample.len() == sample_size {
+                break;
+            }
+
+            if !sample.contains(&i) {
+                sample.push(i);
+            }
+        }
+
+        let bits =
+            sample
+                .iter()
+                .map(|i| fingerprints[*i].clone())
+                .collect::<Vec<_>>();
+
+        let distances = distances(&pairwise_bits(&bits, metric));
+
+        let medoids =
+            pam(&distances, sample_size, options.k, options.max_iterations)
+                .into_iter()
+                .map(|i| sample[i])
+                .collect();
+
+        let candidate = clustering(len, medoids, kind, distance);
+
+        if !matches!(&best, Some((_, cost)) if *cost <= candidate.1) {
+            best = Some(candidate);
+        }
+    }
+
+    best.expect("at least one sample").0
+}
+
+/// Assigns every fingerprint to its closest medoid, returning the clustering and its total
+/// dissimilarity.
+fn clustering(len: usize, mut medoids: Vec<usize>, kind: MetricKind, distance: impl Fn(usize, usize) -> f64) -> (Clustering, f64) {
+    medoids.sort_unstable();
+
+    let (assignments, costs): (Vec<_>, Vec<_>) =
+        (0..len)
+            .map(|j| match medoids.binary_search(&j) {
+                Ok(cluster) => (cluster, distance(j, j)),
+                Err(_) => {
+                    medoids
+                        .iter()
+                        .map(|m| distance(*m, j))
+                        .enumerate()
+                        .fold((0, f64::INFINITY), |best, (cluster, d)| if d < best.1 { (cluster, d) } else { best })
+                }
+            })
+            .unzip();
+
+    let cost = costs.iter().sum();
+
+    (
+        Clustering {
+            assignments,
+            medoids,
+            score: costs.into_iter().map(|d| score(kind, d)).sum(),
+        },
+        cost,
+    )
+}
+
+/// Partitioning Around Medoids: a greedy BUILD followed by the best improving swap until none is
+/// left.
+fn pam(distances: &[f64], len: usize, k: usize, max_iterations: usize) -> Vec<usize> {
+    let at = |i: usize, j: usize| distances[i * len + j];
+
+    let first =
+        (0..len)
+            .map(|i| (i, (0..len).map(|j| at(i, j)).sum::<f64>()))
+            .fold((0, f64::INFINITY), |best, (i, total)| if total < best.1 { (i, total) } else { best })
+            .0;
+
+    let mut medoids = vec![first];
+    let mut nearest = (0..len).map(|j| at(first, j)).collect::<Vec<_>>();
+
+    while medoids.len() < k {
+        let mut best: Option<(usize, f64)> = None;
+
+        for c in (0..len).filter(|c| !medoids.contains(c)) {
+            let gain =
+                (0..len)
+                    .map(|j| -change(at(c, j).min(nearest[j]), nearest[j]))
+                    .sum::<f64>();
+
+            if !matches!(best, Some((_, best_gain)) if best_gain >= gain) {
+                best = Some((c, gain));
+            }
+        }
+
+        let (c, _) = best.expect("k never exceeds the number of fingerprints");
+
+        medoids.push(c);
+
+        for (j, nearest) in nearest.iter_mut().enumerate() {
+            *nearest = nearest.min(at(c, j));
+        }
+    }
+
+    for _ in 0..max_iterations {
+        // the closest medoid of every fingerprint, its distance and the distance to the runner-up
+        let closest =
+            (0..len)
+                .map(|j| {
+                    medoids
+                        .iter()
+                        .enumerate()
+                        .fold((0, f64::INFINITY, f64::INFINITY), |(slot, first, second), (s, m)| {
+                            let d = at(*m, j);
+
+                            if d < first { (s, d, first) } else { (slot, first, second.min(d)) }
+                        })
+                })
+                .collect::<Vec<_>>();
+
+        let mut best: Option<(usize, usize)> = None;
+        let mut best_delta = -1e-9;
+
+        for slot in 0..k {
+            for c in (0..len).filter(|c| !medoids.contains(c)) {
+                let delta =
+                    closest
+                        .iter()
+                        .enumerate()
+                        .map(|(j, (nearest_slot, first, second))| {
+                            let d = at(c, j);
+                            let new = if *nearest_slot == slot { second.min(d) } else { first.min(d) };
+
+                            change(new, *first)
+                        })
+                        .sum::<f64>();
+
+                if delta < best_delta {
+                    best = Some((slot, c));
+                    best_delta = delta;
+                }
+            }
+        }
+
+        match best {
+            Some((slot, c)) => medoids[slot] = c,
+            None => break,
+        }
+    }
+
+    medoids
+}
+
+/// `new - old`, without turning two infinite distances into NaN.
+fn change(new: f64, old: f64) -> f64 {
+    if new == old { 0.0 } else { new - old }
+}
+
+/// Clusters fingerprints of the default 128x128 retina bottom-up.
+pub fn agglomerative(fingerprints: &[Fingerprint], linkage: Linkage, metric: &dyn SimilarityMetric) -> Result<Dendrogram, Error> {
+    let bits =
+        fingerprints
+            .iter()
+            .map(BitFingerprint::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(agglomerative_matrix(&pairwise_bits(&bits, metric), linkage))
+}
+
+/// Clusters precomputed scores bottom-up with the nearest-neighbour chain algorithm, in
+/// `O(len^2)` time and memory.
+pub fn agglomerative_matrix(matrix: &SimilarityMatrix, linkage: Linkage) -> Dendrogram {
+    let (len, kind) = (matrix.len(), matrix.kind());
+
+    let mut distances = distances(matrix);
+    let mut sizes = vec![1usize; len];
+    let mut active = vec![true; len];
+    let mut chain: Vec<usize> = Vec::new();
+    let mut steps: Vec<(usize, usize, f64)> = Vec::with_capacity(len.saturating_sub(1));
+
+    while steps.len() + 1 < len {
+        if chain.is_empty() {
+            chain.push(active.iter().position(|active| *active).expect("two clusters are left"));
+        }
+
+        loop {
+            let a = chain[chain.len() - 1];
+
+            // preferring the previous link on ties keeps the chain from cycling
+            let previous = chain.len().checked_sub(2).map(|i| chain[i]);
+            let mut nearest = previous.map(|p| (p, distances[a * len + p]));
+
+            for c in (0..len).filter(|c| active[*c] && *c != a) {
+                let d = distances[a * len + c];
+
+                if !matches!(nearest, Some((_, nearest)) if nearest <= d) {
+                    nearest = Some((c, d));
+                }
+            }
+
+            let (b, d) = nearest.expect("two clusters are left");
+
+            if Some(b) != previous {
+                chain.push(b);
+                continue;
+            }
+
+            chain.truncate(chain.len() - 2);
+
+            // the merged cluster takes the place of `b`
+            for c in (0..len).filter(|c| active[*c] && *c != a && *c != b) {
+                let (ac, bc) = (distances[a * len + c], distances[b * len + c]);
+
+                let merged =
+                    match linkage {
+                        Linkage::Single => ac.min(bc),
+                        Linkage::Complete => ac.max(bc),
+                        Linkage::Average => (sizes[a] as f64 * ac + sizes[b] as f64 * bc) / (sizes[a] + sizes[b]) as f64,
+                    };
+
+                distances[b * len + c] = merged;
+                distances[c * len + b] = merged;
+            }
+
+            active[a] = false;
+            sizes[b] += sizes[a];
+            steps.push((a, b, d));
+
+            break;
+        }
+    }
+
+    // the chain finds merges out of order; sort them and relabel the clusters
+    steps.sort_by(|l, r| l.2.total_cmp(&r.2));
+
+    let mut parent = (0..len).collect::<Vec<_>>();
+    let mut ids = (0..len).collect::<Vec<_>>();
+    let mut sizes = vec![1usize; len];
+
+    let merges =
+        steps
+            .into_iter()
+            .enumerate()
+            .map(|(i, (a, b, d))| {
+                let (a, b) = (find(&mut parent, a), find(&mut parent, b));
+                let (left, right) = (ids[a].min(ids[b]), ids[a].max(ids[b]));
+
+                parent[a] = b;
+                ids[b] = len + i;
+                sizes[b] += sizes[a];
+
+                Merge {
+                    left,
+                    right,
+                    score: score(kind, d),
+                    size: sizes[b],
+                }
+            })
+            .collect();
+
+    Dendrogram {
+        len,
+        kind,
+        linkage,
+        merges,
+    }
+}
+
+/// Every score as a dissimilarity, lower is closer, in a dense `len * len` matrix.
+fn distances(matrix: &SimilarityMatrix) -> Vec<f64> {
+    let len = matrix.len();
+
+    (0..len * len)
+        .map(|i| dissimilarity(matrix.kind(), matrix.get(i / len, i % len)))
+        .collect()
+}
+
+/// Negating similarities makes every linkage and the k-medoids cost work on distances alone; NaN
+/// scores are as far apart as possible.
+fn dissimilarity(kind: MetricKind, score: f64) -> f64 {
+    match kind {
+        _ if score.is_nan() => f64::INFINITY,
+        MetricKind::Distance => score,
+        MetricKind::Similarity => -score,
+    }
+}
+
+fn score(kind: MetricKind, dissimilarity: f64) -> f64 {
+    match kind {
+        MetricKind::Distance => dissimilarity,
+        MetricKind::Similarity => -dissimilarity,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metric::{Hamming, Jaccard, JaccardDistance};
+    use crate::{Error, Fingerprint};
+
+    use super::{agglomerative, k_medoids, Dendrogram, KMedoids, Linkage, Merge};
+
+    /// `groups` topics of `size` fingerprints each, every fingerprint a slight variation of its
+    /// topic.
+    fn topics(groups: u32, size: u32) -> Vec<Fingerprint> {
+        (0..groups * size)
+            .map(|i| {
+                let (topic, variant) = (i % groups, i / groups);
+                let base = topic * 1000;
+
+                Fingerprint { positions: (base + variant..base + 40 + 2 * variant).collect() }
+            })
+            .collect()
+    }
+
+    fn assert_groups(assignments: &[usize], groups: u32) {
+        for (i, cluster) in assignments.iter().enumerate() {
+            assert_eq!(*cluster, assignments[i % groups as usize], "fingerprint {}", i);
+        }
+
+        let mut distinct = assignments.to_vec();
+        distinct.sort_unstable();
+        distinct.dedup();
+
+        assert_eq!(distinct.len(), groups as usize);
+    }
+
+    #[test]
+    fn k_medoids_finds_topics() {
+        let fingerprints = topics(3, 6);
+
+        let clustering = k_medoids(&fingerprints, KMedoids::new(3), &Jaccard).unwrap();
+
+        assert_groups(&clustering.assignments, 3);
+        assert_eq!(clustering.medoids.len(), 3);
+        assert_eq!(clustering.clusters().iter().map(Vec::len).collect::<Vec<_>>(), vec![6, 6, 6]);
+
+        for (cluster, medoid) in clustering.medoids.iter().enumerate() {
+            assert_eq!(clustering.assignments[*medoid], cluster);
+        }
+
+        // distances cluster the same way
+        let distance = k_medoids(&fingerprints, KMedoids::new(3), &JaccardDistance).unwrap();
+
+        assert_eq!(distance.assignments, clustering.assignments);
+        assert_eq!(distance.medoids, clustering.medoids);
+        assert!((distance.score - (18.0 - clustering.score)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn clara_samples_reproducibly() {
+        let fingerprints = topics(4, 15);
+        let options = KMedoids::new(4).with_sample_size(20).with_seed(7);
+
+        let clustering = k_medoids(&fingerprints, options, &Jaccard).unwrap();
+
+        assert_groups(&clustering.assignments, 4);
+        assert_eq!(clustering, k_medoids(&fingerprints, options, &Jaccard).unwrap());
+    }
+
+    #[test]
+    fn rejects_bad_k() {
+        let fingerprints = topics(1, 3);
+
+        assert!(matches!(k_medoids(&fingerprints, KMedoids::new(0), &Jaccard), Err(Error::Config(_))));
+        assert!(matches!(k_medoids(&fingerprints, KMedoids::new(4), &Jaccard), Err(Error::Config(_))));
+        assert_eq!(k_medoids(&fingerprints, KMedoids::new(3), &Jaccard).unwrap().medoids, vec![0, 1, 2]);
+    }
+
+    fn chain() -> Vec<Fingerprint> {
+        [
+            vec![1, 2, 3, 4],
+            vec![1, 2, 3, 4, 5],
+            vec![1, 2, 3, 4, 5, 6, 7],
+            vec![100, 101],
+        ]
+            .into_iter()
+            .map(|positions| Fingerprint { positions })
+            .collect()
+    }
+
+    fn merge(left: usize, right: usize, score: f64, size: usize) -> Merge {
+        Merge { left, right, score, size }
+    }
+
+    #[test]
+    fn linkages() {
+        let single = agglomerative(&chain(), Linkage::Single, &Hamming).unwrap();
+
+        assert_eq!(single.merges, vec![merge(0, 1, 1.0, 2), merge(2, 4, 2.0, 3), merge(3, 5, 6.0, 4)]);
+
+        let complete = agglomerative(&chain(), Linkage::Complete, &Hamming).unwrap();
+
+        assert_eq!(complete.merges, vec![merge(0, 1, 1.0, 2), merge(2, 4, 3.0, 3), merge(3, 5, 9.0, 4)]);
+
+        let average = agglomerative(&chain(), Linkage::Average, &Hamming).unwrap();
+
+        assert_eq!(average.merges, vec![merge(0, 1, 1.0, 2), merge(2, 4, 2.5, 3), merge(3, 5, 22.0 / 3.0, 4)]);
+    }
+
+    #[test]
+    fn dendrogram_cuts_and_serializes() {
+        let dendrogram = agglomerative(&chain(), Linkage::Single, &Hamming).unwrap();
+
+        assert_eq!(dendrogram.cut(1), vec![0, 0, 0, 0]);
+        assert_eq!(dendrogram.cut(2), vec![0, 0, 0, 1]);
+        assert_eq!(dendrogram.cut(10), vec![0, 1, 2, 3]);
+        assert_eq!(dendrogram.cut_at(2.0), vec![0, 0, 0, 1]);
+        assert_eq!(dendrogram.cut_at(1.5), vec![0, 0, 1, 2]);
+
+        let json = serde_json::to_string(&dendrogram).unwrap();
+
+        assert_eq!(serde_json::from_str::<Dendrogram>(&json).unwrap(), dendrogram);
+
+        // similarities merge best first as well
+        let similarity = agglomerative(&topics(2, 4), Linkage::Average, &Jaccard).unwrap();
+
+        assert!(similarity.merges.windows(2).all(|pair| pair[0].score >= pair[1].score));
+        assert_eq!(similarity.cut(2), vec![0, 1, 0, 1, 0, 1, 0, 1]);
+        assert_eq!(similarity.cut_at(0.0), vec![0; 8]);
+    }
+}
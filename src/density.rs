@@ -1,8 +1,10 @@
+use std::cmp::Ordering;
 use std::collections::BTreeSet;
 
-use num::Float;
-use num_traits::{FromPrimitive, Zero};
+use num_traits::Zero;
 use crate::find_peaks::PeakFinder;
+use crate::similarity::sorted_positions;
+use crate::{target_len, Error, Fingerprint, Grid, RetinaShape};
 
 pub fn gaussian(x1: f32, y1: f32, x2: f32, y2: f32, radius: f32) -> f32 {
     let pi = std::f32::consts::PI;
@@ -10,23 +12,22 @@ pub fn gaussian(x1: f32, y1: f32, x2: f32, y2: f32, radius: f32) -> f32 {
     let denominator = 0.4;
 
     let exponent =
-        -1.0
+        -(
+            3.0
             * (
-                3.0
-                * (
-                    (x1 - x2).powi(2)
-                    + (y1 - y2).powi(2)
-                ).sqrt()
-                / radius
-            ).powi(2)
-            / denominator;
+                (x1 - x2).powi(2)
+                + (y1 - y2).powi(2)
+            ).sqrt()
+            / radius
+        ).powi(2)
+        / denominator;
 
     let exponent = exponent.exp();
 
     numerator * exponent
 }
 
-pub fn kde(x: f32, y: f32, points: &Vec<(f32, f32)>, radius: f32) -> f32 {
+pub fn kde(x: f32, y: f32, points: &[(f32, f32)], radius: f32) -> f32 {
     let mut sum = 0.0;
 
     for point in points.iter() {
@@ -36,28 +37,13 @@ pub fn kde(x: f32, y: f32, points: &Vec<(f32, f32)>, radius: f32) -> f32 {
     sum
 }
 
-fn amax(
-    vec: &[f32; 16384],
-) -> (usize, f32) {
-    let mut max = f32::zero();
-    let mut pos = 0;
-
-    for (i, val) in vec.iter().enumerate() {
-        if *val > max {
-            max = *val;
-            pos = i;
-        }
-    }
-
-    (pos, max)
-}
-
 const LOCALITY: f32 = 5.0f32;
 
 pub struct Kde {
-    pub data: [u32; 16384],
+    pub data: Grid<u32>,
+    /// The `(x, y)` of every non-zero cell of `data`.
     pub points: Vec<(f32, f32)>,
-    pub kde: [f32; 16384],
+    pub kde: Grid<f32>,
 
     pub densest_points: BTreeSet<usize>,
 
@@ -70,16 +56,11 @@ pub struct Kde {
 }
 
 impl Kde {
-    pub fn new(vec: &[u32; 16384]) -> Kde {
-        let mut data = [0u32; 16384];
-
-        data.copy_from_slice(vec.as_slice());
-
-
+    pub fn new(data: Grid<u32>) -> Kde {
         Kde {
-            data,
             points: Vec::new(),
-            kde: [0.0; 16384],
+            kde: Grid::filled(data.shape(), 0.0),
+            data,
 
             densest_points: BTreeSet::new(),
 
@@ -92,9 +73,13 @@ impl Kde {
         }
     }
 
+    pub fn shape(&self) -> RetinaShape {
+        self.data.shape()
+    }
+
     pub fn clear(&mut self) {
         self.points = Vec::new();
-        self.kde = [0.0f32; 16384];
+        self.kde = Grid::filled(self.shape(), 0.0f32);
 
         self.densest_points = BTreeSet::new();
 
@@ -107,16 +92,16 @@ impl Kde {
     }
 
     pub fn build_points(&mut self) {
-        for y in 0..16384 {
-            if self.data[y] <= 0 {
+        for ((x, y), value) in self.data.cells() {
+            if *value == 0 {
                 continue;
             }
 
             self.points
                 .push(
                     (
-                        (y / 128) as f32,
-                        (y % 128) as f32
+                        x as f32,
+                        y as f32
                     )
                 );
         }
@@ -124,10 +109,10 @@ impl Kde {
 
     pub fn determine_kde_params(&mut self) -> Option<()> {
         // find the min and max of x and y respectively
-        self.x_min = self.points.iter().map(|p| p.0).min_by(|a, b| a.partial_cmp(b).unwrap()).unwrap();
-        self.x_max = self.points.iter().map(|p| p.0).max_by(|a, b| a.partial_cmp(b).unwrap()).unwrap();
-        self.y_min = self.points.iter().map(|p| p.1).min_by(|a, b| a.partial_cmp(b).unwrap()).unwrap();
-        self.y_max = self.points.iter().map(|p| p.1).max_by(|a, b| a.partial_cmp(b).unwrap()).unwrap();
+        self.x_min = self.points.iter().map(|p| p.0).min_by(|a, b| a.partial_cmp(b).unwrap())?;
+        self.x_max = self.points.iter().map(|p| p.0).max_by(|a, b| a.partial_cmp(b).unwrap())?;
+        self.y_min = self.points.iter().map(|p| p.1).min_by(|a, b| a.partial_cmp(b).unwrap())?;
+        self.y_max = self.points.iter().map(|p| p.1).max_by(|a, b| a.partial_cmp(b).unwrap())?;
 
         let dx = self.x_max - self.x_min;
         let dy = self.y_max - self.y_min;
@@ -138,54 +123,46 @@ impl Kde {
     }
 
     pub fn calculate_kde(&mut self) {
-        for y in 0..128 {
-            for x in 0..128 {
-                self.kde[(x * 128) + y] =
-                    kde(
-                        x as f32,
-                        y as f32,
-                        &self.points,
-                        self.radius,
-                    );
-            }
-        }
+        self.kde =
+            Grid::from_fn(self.shape(), |x, y| {
+                kde(
+                    x as f32,
+                    y as f32,
+                    &self.points,
+                    self.radius,
+                )
+            });
     }
 
     pub fn determine_densest_points(&mut self) {
         let mut pf =
-            PeakFinder::new(&self.kde);
+            PeakFinder::new(self.kde.as_slice());
 
         pf.with_min_prominence(2.0);
 
         let peaks =
-            pf.find_peaks();
-
-        let peaks =
-            peaks.iter()
-                .map(|p| p.clone().position.collect::<Vec<_>>())
-                .flatten()
+            pf.find_peaks()
+                .into_iter()
+                .flat_map(|p| p.position)
+                .map(|peak| self.kde.coordinates(peak))
                 .collect::<Vec<_>>();
 
         // find the densest area in kde_vec
-        for peak in peaks {
-            let (x, y) = (peak / 128, peak % 128);
-
+        for (x, y) in peaks {
             let cutoff = 10.0;
 
-            // find all points within 5 pixels of the densest point in "points"
+            // find all points within 10 cells of the densest point in "points"
             for point in self.points.iter() {
                 if (point.0 - x as f32).abs() < cutoff
                     && (point.1 - y as f32).abs() < cutoff {
-                    let idx = point.0 as u32 * 128 + point.1 as u32;
+                    let (px, py) = (point.0 as usize, point.1 as usize);
 
-                    if self.data[idx as usize] > 0 {
-                        self.densest_points.insert(idx as usize);
+                    if self.data[(px, py)] > 0 {
+                        self.densest_points.insert(self.data.position(px, py));
                     }
                 }
             }
         }
-
-        dbg!(&self.densest_points);
     }
 
     pub fn fit_kde(&mut self) {
@@ -193,7 +170,7 @@ impl Kde {
             self.kde
                 .iter()
                 .fold(
-                    (std::f32::MAX, std::f32::MIN),
+                    (f32::MAX, f32::MIN),
                     |(min, max), &x|
                         (
                             min.min(x),
@@ -209,19 +186,18 @@ impl Kde {
             );
     }
 
-    pub fn get_kde_data(&self) -> [u32; 16384] {
-        let mut kde_data = [0u32; 16384];
-
-        for (i, val) in self.kde.iter().enumerate() {
-            kde_data[i] = *val as u32;
-        }
-
-        kde_data
+    pub fn get_kde_data(&self) -> Grid<u32> {
+        self.kde
+            .map(|val| *val as u32)
     }
 
     pub fn run(&mut self) {
         self.build_points();
-        self.determine_kde_params();
+
+        if self.determine_kde_params().is_none() {
+            return;
+        }
+
         self.calculate_kde();
         self.determine_densest_points();
         self.fit_kde();
@@ -229,31 +205,38 @@ impl Kde {
 }
 
 pub struct Density {
-    pub data: [u32; 16384],
+    pub data: Grid<u32>,
 }
 
 impl Density {
-    pub fn new(vec: &[u32]) -> Self {
-        assert_eq!(vec.len(), 16384);
-
-        let mut data = [0u32; 16384];
-
-        data.copy_from_slice(vec);
-
+    pub fn new(data: Grid<u32>) -> Self {
         Self {
             data,
         }
     }
 
-    pub fn set_data(&mut self, vec: &[u32; 16384]) {
-        let mut data = [0u32; 16384];
+    /// Counts how many of `fingerprints` hit every position of `shape`.
+    pub fn from_fingerprints(fingerprints: &[Fingerprint], shape: RetinaShape) -> Result<Self, Error> {
+        let mut data = Grid::filled(shape, 0u32);
 
-        data.copy_from_slice(vec.as_slice());
+        for fingerprint in fingerprints {
+            for position in sorted_positions(fingerprint, shape)? {
+                data.as_mut_slice()[position as usize] += 1;
+            }
+        }
+
+        Ok(Self::new(data))
+    }
+
+    pub fn shape(&self) -> RetinaShape {
+        self.data.shape()
+    }
 
+    pub fn set_data(&mut self, data: Grid<u32>) {
         self.data = data;
     }
 
-    pub fn get_data(&self) -> &[u32; 16384] {
+    pub fn get_data(&self) -> &Grid<u32> {
         &self.data
     }
 
@@ -264,12 +247,278 @@ impl Density {
             .for_each(|b| *b = u32::zero());
     }
 
-    pub fn kde(&mut self) -> Kde {
+    pub fn kde(&self) -> Kde {
         let mut kde =
-            Kde::new(&self.data);
+            Kde::new(self.data.clone());
 
         kde.run();
 
         kde
     }
-}
\ No newline at end of file
+}
+
+/// How [`Fingerprint::thin`] picks the positions to keep.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Thinning {
+    /// The positions with the most active neighbours at most this many rows and columns away.
+    Support(usize),
+    /// The positions where the kernel density estimate of the fingerprint is highest.
+    Density,
+}
+
+impl Fingerprint {
+    /// The share of the positions of `shape` that are active.
+    pub fn sparsity(&self, shape: RetinaShape) -> Result<f64, Error> {
+        Ok(sorted_positions(self, shape)?.len() as f64 / shape.len() as f64)
+    }
+
+    /// Thins or densifies the fingerprint until `sparsity` of `shape` is active, so fingerprints
+    /// of short and long texts overlap on equal terms.
+    pub fn with_sparsity(&self, sparsity: f64, thinning: Thinning, shape: RetinaShape) -> Result<Fingerprint, Error> {
+        let len = target_len(sparsity, shape)?;
+
+        let positions = sorted_positions(self, shape)?;
+
+        let positions =
+            match positions.len().cmp(&len) {
+                Ordering::Greater => thin(positions, len, thinning, shape),
+                Ordering::Less => densify(positions, len, shape),
+                Ordering::Equal => positions,
+            };
+
+        Ok(Fingerprint { positions })
+    }
+
+    /// Keeps the `len` best supported positions. Ties go to the lower position.
+    pub fn thin(&self, len: usize, thinning: Thinning, shape: RetinaShape) -> Result<Fingerprint, Error> {
+        Ok(Fingerprint { positions: thin(sorted_positions(self, shape)?, len, thinning, shape) })
+    }
+
+    /// Dilates the densest regions of the fingerprint until `len` positions are active, adding the
+    /// inactive positions with the highest kernel density first. An empty fingerprint stays empty.
+    pub fn densify(&self, len: usize, shape: RetinaShape) -> Result<Fingerprint, Error> {
+        Ok(Fingerprint { positions: densify(sorted_positions(self, shape)?, len, shape) })
+    }
+}
+
+/// [`Fingerprint::thin`] on sorted, deduplicated positions.
+fn thin(positions: Vec<u32>, len: usize, thinning: Thinning, shape: RetinaShape) -> Vec<u32> {
+    if positions.len() <= len {
+        return positions;
+    }
+
+    let scores =
+        match thinning {
+            Thinning::Support(radius) => support(&positions, radius, shape),
+            Thinning::Density => {
+                let density = kde_of(&positions, shape);
+
+                positions
+                    .iter()
+                    .map(|position| {
+                        let (x, y) = shape.coordinates(*position as usize);
+
+                        kde(x as f32, y as f32, &density.points, density.radius)
+                    })
+                    .collect()
+            }
+        };
+
+    let mut ranked = (0..positions.len()).collect::<Vec<_>>();
+
+    ranked.sort_by(|a, b| scores[*b].total_cmp(&scores[*a]).then(a.cmp(b)));
+    ranked.truncate(len);
+    ranked.sort_unstable();
+
+    ranked.into_iter().map(|i| positions[i]).collect()
+}
+
+/// [`Fingerprint::densify`] on sorted, deduplicated positions.
+fn densify(mut positions: Vec<u32>, len: usize, shape: RetinaShape) -> Vec<u32> {
+    let len = len.min(shape.len());
+
+    // the estimate fades a few radii out, so growing far beyond the fingerprint takes rounds
+    while positions.len() < len && !positions.is_empty() {
+        let mut density = kde_of(&positions, shape);
+
+        density.calculate_kde();
+
+        let estimate = density.kde.as_slice();
+
+        let mut candidates =
+            density.data
+                .to_fingerprint(|value| *value == 0)
+                .positions
+                .into_iter()
+                .map(|position| position as usize)
+                .filter(|position| estimate[*position] > 0.0)
+                .collect::<Vec<_>>();
+
+        if candidates.is_empty() {
+            break;
+        }
+
+        candidates.sort_by(|a, b| estimate[*b].total_cmp(&estimate[*a]).then(a.cmp(b)));
+        candidates.truncate(len - positions.len());
+
+        positions.extend(candidates.into_iter().map(|position| position as u32));
+        positions.sort_unstable();
+    }
+
+    positions
+}
+
+/// The number of other active positions in the window of `radius` around every position.
+fn support(positions: &[u32], radius: usize, shape: RetinaShape) -> Vec<f32> {
+    let active = active(positions, shape, false, true);
+
+    positions
+        .iter()
+        .map(|position| {
+            let (x, y) = active.coordinates(*position as usize);
+
+            let neighbours =
+                active
+                    .window(x, y, radius)
+                    .filter(|(_, active)| **active)
+                    .count();
+
+            (neighbours - 1) as f32
+        })
+        .collect()
+}
+
+/// A [`Kde`] over the active positions, without running it. The radius is at least one cell, so
+/// fingerprints on a single row or column still spread.
+fn kde_of(positions: &[u32], shape: RetinaShape) -> Kde {
+    let mut density = Kde::new(active(positions, shape, 0, 1));
+
+    density.build_points();
+    density.determine_kde_params();
+    density.radius = density.radius.max(1.0);
+
+    density
+}
+
+/// `on` at `positions`, which have been checked against `shape`, `off` everywhere else.
+fn active<T: Clone>(positions: &[u32], shape: RetinaShape, off: T, on: T) -> Grid<T> {
+    let mut grid = Grid::filled(shape, off);
+
+    for position in positions {
+        grid.as_mut_slice()[*position as usize] = on.clone();
+    }
+
+    grid
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Error, Fingerprint, Grid, RetinaShape};
+
+    use super::{Density, Thinning};
+
+    #[test]
+    fn follows_the_retina_shape() {
+        let shape = RetinaShape::new(20, 40);
+
+        // rows 5..10, columns 25..32
+        let data = Grid::from_fn(shape, |x, y| if (25..32).contains(&x) && (5..10).contains(&y) { 100 } else { 0 });
+
+        let kde =
+            Density::new(data)
+                .kde();
+
+        assert_eq!(kde.get_kde_data().shape(), shape);
+
+        // x runs along the columns, y along the rows
+        assert_eq!((kde.x_min, kde.x_max, kde.y_min, kde.y_max), (25.0, 31.0, 5.0, 9.0));
+        assert_eq!(kde.kde.rows().count(), 20);
+        assert_eq!(kde.points.first(), Some(&(25.0, 5.0)));
+    }
+
+    #[test]
+    fn empty_data_has_no_density() {
+        let kde =
+            Density::new(Grid::filled(RetinaShape::new(4, 4), 0))
+                .kde();
+
+        assert!(kde.points.is_empty());
+        assert!(kde.densest_points.is_empty());
+    }
+
+    #[test]
+    fn counts_fingerprint_hits() {
+        let fingerprints = [
+            Fingerprint { positions: vec![0, 3, 3] },
+            Fingerprint { positions: vec![3, 5] },
+        ];
+
+        let density = Density::from_fingerprints(&fingerprints, RetinaShape::new(2, 3)).unwrap();
+
+        assert_eq!(density.get_data().as_slice(), &[1, 0, 0, 2, 0, 1]);
+        assert!(matches!(
+            Density::from_fingerprints(&fingerprints, RetinaShape::new(1, 4)),
+            Err(Error::PositionOutOfRange { position: 5, len: 4 })
+        ));
+    }
+
+    /// A 3x3 block at rows and columns 2..5 of a 10x10 grid, plus three isolated corners.
+    fn block_and_noise() -> Fingerprint {
+        let shape = RetinaShape::new(10, 10);
+
+        let mut positions =
+            (2..5)
+                .flat_map(|y| (2..5).map(move |x| shape.position(x, y) as u32))
+                .collect::<Vec<_>>();
+
+        positions.extend([9, 90, 99]);
+
+        Fingerprint { positions }
+    }
+
+    #[test]
+    fn thins_to_the_supported_positions() {
+        let shape = RetinaShape::new(10, 10);
+        let fingerprint = block_and_noise();
+        let block = fingerprint.positions[..9].to_vec();
+
+        assert_eq!(fingerprint.thin(9, Thinning::Support(1), shape).unwrap().positions, block);
+        assert_eq!(fingerprint.thin(9, Thinning::Density, shape).unwrap().positions, block);
+        assert_eq!(fingerprint.with_sparsity(0.09, Thinning::Support(1), shape).unwrap().positions, block);
+
+        // the centre of the block has the most support
+        assert_eq!(fingerprint.thin(1, Thinning::Support(1), shape).unwrap().positions, vec![33]);
+        assert_eq!(fingerprint.thin(20, Thinning::Density, shape).unwrap().positions.len(), 12);
+        assert_eq!(fingerprint.sparsity(shape).unwrap(), 0.12);
+    }
+
+    #[test]
+    fn densifies_around_the_densest_region() {
+        let shape = RetinaShape::new(20, 20);
+
+        let block =
+            (5..8)
+                .flat_map(|y| (5..8).map(move |x| shape.position(x, y) as u32))
+                .collect::<Vec<_>>();
+
+        let fingerprint = Fingerprint { positions: block.clone() };
+
+        // the ring around the block, without its corners
+        let dense = fingerprint.densify(21, shape).unwrap();
+
+        assert_eq!(dense.positions.len(), 21);
+        assert!(block.iter().all(|position| dense.positions.contains(position)));
+
+        for position in dense.positions.iter() {
+            let (x, y) = shape.coordinates(*position as usize);
+
+            assert!((4..=8).contains(&x) && (4..=8).contains(&y));
+            assert!(![(4, 4), (4, 8), (8, 4), (8, 8)].contains(&(x, y)));
+        }
+
+        // growing past the reach of the estimate takes several rounds
+        assert_eq!(fingerprint.with_sparsity(0.25, Thinning::Density, shape).unwrap().positions.len(), 100);
+        assert_eq!(Fingerprint::default().densify(10, shape).unwrap(), Fingerprint::default());
+        assert!(matches!(fingerprint.with_sparsity(2.0, Thinning::Density, shape), Err(Error::Config(_))));
+    }
+}
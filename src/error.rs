@@ -0,0 +1,259 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::RetinaShape;
+
+/// Errors returned by the Cortical.io client and the fingerprint utilities.
+#[derive(Debug)]
+pub enum Error {
+    /// The request could not be sent or the response could not be read.
+    #[cfg(feature = "client")]
+    Transport(reqwest::Error),
+    /// The API answered with a non-success status code.
+    Http(Box<ApiError>),
+    /// The response body could not be decoded into the expected type.
+    Deserialize {
+        source: serde_json::Error,
+        payload: String,
+    },
+    /// The request body could not be encoded.
+    Serialize(serde_json::Error),
+    /// The API rejected the requested retina.
+    InvalidRetina(Box<ApiError>),
+    /// The account quota or rate limit has been exhausted.
+    Quota(Box<ApiError>),
+    /// The client or another configuration is invalid.
+    Config(String),
+    /// A base64 payload could not be decoded.
+    Base64(base64::DecodeError),
+    /// An image could not be decoded.
+    #[cfg(feature = "image")]
+    Image(image::ImageError),
+    /// A fingerprint position does not fit on the retina.
+    PositionOutOfRange {
+        position: u32,
+        len: usize,
+    },
+    /// A dense vector does not have one value per position of the retina.
+    ShapeMismatch {
+        expected: usize,
+        actual: usize,
+    },
+    /// Two grids that have to line up are laid out on different retinas.
+    RetinaMismatch {
+        expected: RetinaShape,
+        actual: RetinaShape,
+    },
+    /// A bulk endpoint returned a different number of results than it was sent inputs.
+    BulkLength {
+        expected: usize,
+        actual: usize,
+    },
+    /// A MinHash signature does not have one value per hash function of the hasher.
+    SignatureLength {
+        expected: usize,
+        actual: usize,
+    },
+    /// The metric can't be used for the requested operation.
+    UnsupportedMetric {
+        metric: String,
+        reason: &'static str,
+    },
+}
+
+/// The error document the API sends along with a non-success status.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApiErrorBody {
+    pub status: Option<u16>,
+    pub error: Option<String>,
+    pub message: Option<String>,
+    pub path: Option<String>,
+    #[serde(rename = "requestId")]
+    pub request_id: Option<String>,
+}
+
+/// A failed API call, kept together with the context needed to report it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApiError {
+    pub status: u16,
+    pub endpoint: String,
+    pub request_id: Option<String>,
+    pub body: Option<ApiErrorBody>,
+    pub raw: String,
+}
+
+impl ApiError {
+    pub fn new(status: u16, endpoint: &str, request_id: Option<String>, raw: String) -> Self {
+        let body =
+            serde_json::from_str::<ApiErrorBody>(&raw)
+                .ok();
+
+        let request_id =
+            request_id.or_else(|| body.as_ref().and_then(|body| body.request_id.clone()));
+
+        Self {
+            status,
+            endpoint: endpoint.to_string(),
+            request_id,
+            body,
+            raw,
+        }
+    }
+
+    /// The most descriptive message available: the decoded one or the raw body.
+    pub fn message(&self) -> &str {
+        self.body
+            .as_ref()
+            .and_then(|body| body.message.as_deref().or(body.error.as_deref()))
+            .unwrap_or(&self.raw)
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "HTTP {} from {}", self.status, self.endpoint)?;
+
+        if let Some(request_id) = &self.request_id {
+            write!(f, " (request id {})", request_id)?;
+        }
+
+        write!(f, ": {}", self.message())
+    }
+}
+
+impl Error {
+    /// Classifies a failed API call by its status code and message.
+    pub fn from_api(error: ApiError) -> Self {
+        match error.status {
+            429 => Error::Quota(Box::new(error)),
+            400 | 404 if error.message().to_lowercase().contains("retina") => Error::InvalidRetina(Box::new(error)),
+            _ => Error::Http(Box::new(error)),
+        }
+    }
+
+    /// The details of the failed API call, if the API answered at all.
+    pub fn api_error(&self) -> Option<&ApiError> {
+        match self {
+            Error::Http(error)
+            | Error::InvalidRetina(error)
+            | Error::Quota(error) => Some(error),
+            _ => None,
+        }
+    }
+
+    /// The HTTP status code of the failed response, if there was one.
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            #[cfg(feature = "client")]
+            Error::Transport(err) => err.status().map(|status| status.as_u16()),
+            _ => self.api_error().map(|error| error.status),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "client")]
+            Error::Transport(err) => write!(f, "transport error: {}", err),
+            Error::Http(error) => write!(f, "{}", error),
+            Error::Deserialize { source, .. } => write!(f, "failed to decode response: {}", source),
+            Error::Serialize(err) => write!(f, "failed to encode request: {}", err),
+            Error::InvalidRetina(error) => write!(f, "invalid retina: {}", error),
+            Error::Quota(error) => write!(f, "quota exceeded: {}", error),
+            Error::Config(message) => write!(f, "invalid configuration: {}", message),
+            Error::Base64(err) => write!(f, "invalid base64 payload: {}", err),
+            #[cfg(feature = "image")]
+            Error::Image(err) => write!(f, "invalid image: {}", err),
+            Error::PositionOutOfRange { position, len } => write!(f, "position {} is outside a retina of {} positions", position, len),
+            Error::ShapeMismatch { expected, actual } => write!(f, "expected {} values, one per retina position, got {}", expected, actual),
+            Error::RetinaMismatch { expected, actual } => write!(f, "expected a grid of {} rows and {} columns, got {} rows and {} columns", expected.rows, expected.columns, actual.rows, actual.columns),
+            Error::BulkLength { expected, actual } => write!(f, "bulk request returned {} results for {} inputs", actual, expected),
+            Error::SignatureLength { expected, actual } => write!(f, "expected a signature of {} values, got {}", expected, actual),
+            Error::UnsupportedMetric { metric, reason } => write!(f, "unsupported metric {}: {}", metric, reason),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            #[cfg(feature = "client")]
+            Error::Transport(err) => Some(err),
+            Error::Deserialize { source, .. } => Some(source),
+            Error::Serialize(err) => Some(err),
+            Error::Base64(err) => Some(err),
+            #[cfg(feature = "image")]
+            Error::Image(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "client")]
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        Error::Transport(err)
+    }
+}
+
+impl From<base64::DecodeError> for Error {
+    fn from(err: base64::DecodeError) -> Self {
+        Error::Base64(err)
+    }
+}
+
+#[cfg(feature = "image")]
+impl From<image::ImageError> for Error {
+    fn from(err: image::ImageError) -> Self {
+        Error::Image(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ApiError, Error};
+
+    #[test]
+    fn classifies_status() {
+        let error = |status, raw: &str| Error::from_api(ApiError::new(status, "/rest/text", None, raw.to_string()));
+
+        assert!(matches!(error(429, ""), Error::Quota(_)));
+        assert!(matches!(error(400, "Retina 'xx' does not exist"), Error::InvalidRetina(_)));
+        assert!(matches!(error(500, "oops"), Error::Http(_)));
+        assert_eq!(error(503, "").status(), Some(503));
+    }
+
+    #[test]
+    fn decodes_error_body() {
+        let error =
+            ApiError::new(
+                400,
+                "/rest/compare",
+                None,
+                r#"{"status":400,"error":"Bad Request","message":"Invalid retina name: xx","path":"/rest/compare","requestId":"abc-123"}"#.to_string(),
+            );
+
+        assert_eq!(error.message(), "Invalid retina name: xx");
+        assert_eq!(error.request_id.as_deref(), Some("abc-123"));
+        assert_eq!(error.endpoint, "/rest/compare");
+        assert_eq!(
+            error.to_string(),
+            "HTTP 400 from /rest/compare (request id abc-123): Invalid retina name: xx"
+        );
+
+        let error = ApiError::new(502, "/rest/text", Some("req-1".to_string()), "<html>Bad Gateway</html>".to_string());
+
+        assert_eq!(error.body, None);
+        assert_eq!(error.message(), "<html>Bad Gateway</html>");
+        assert_eq!(error.request_id.as_deref(), Some("req-1"));
+    }
+
+    #[test]
+    fn is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+
+        assert_send_sync::<Error>();
+    }
+}
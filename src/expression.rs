@@ -0,0 +1,275 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{Fingerprint, PosType, TextEnvelope};
+
+/// A semantic expression as accepted by the `/expressions` endpoints.
+///
+/// Leaves are terms, texts or raw fingerprint positions; the operators combine the fingerprints
+/// of their operands. Serializes to the API's JSON, e.g. `{"and":[{"term":"jaguar"},{"text":"..."}]}`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Expression {
+    #[serde(rename = "term")]
+    Term(String),
+    #[serde(rename = "text")]
+    Text(String),
+    #[serde(rename = "positions")]
+    Positions(Vec<u32>),
+    #[serde(rename = "and")]
+    And(Vec<Expression>),
+    #[serde(rename = "or")]
+    Or(Vec<Expression>),
+    #[serde(rename = "sub")]
+    Sub(Vec<Expression>),
+    #[serde(rename = "xor")]
+    Xor(Vec<Expression>),
+}
+
+impl Expression {
+    pub fn term(term: &str) -> Self {
+        Expression::Term(term.to_string())
+    }
+
+    pub fn text(text: &str) -> Self {
+        Expression::Text(text.to_string())
+    }
+
+    pub fn positions(positions: &[u32]) -> Self {
+        Expression::Positions(positions.to_vec())
+    }
+
+    pub fn and(operands: impl IntoIterator<Item=Expression>) -> Self {
+        Expression::And(operands.into_iter().collect())
+    }
+
+    pub fn or(operands: impl IntoIterator<Item=Expression>) -> Self {
+        Expression::Or(operands.into_iter().collect())
+    }
+
+    /// The first operand minus every following operand.
+    pub fn sub(operands: impl IntoIterator<Item=Expression>) -> Self {
+        Expression::Sub(operands.into_iter().collect())
+    }
+
+    pub fn xor(operands: impl IntoIterator<Item=Expression>) -> Self {
+        Expression::Xor(operands.into_iter().collect())
+    }
+}
+
+impl From<Fingerprint> for Expression {
+    fn from(fingerprint: Fingerprint) -> Self {
+        Expression::Positions(fingerprint.positions)
+    }
+}
+
+impl From<&Fingerprint> for Expression {
+    fn from(fingerprint: &Fingerprint) -> Self {
+        Expression::Positions(fingerprint.positions.clone())
+    }
+}
+
+impl From<TextEnvelope> for Expression {
+    fn from(envelope: TextEnvelope) -> Self {
+        Expression::Text(envelope.text)
+    }
+}
+
+/// Query parameters of `/expressions` and `/expressions/bulk`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GetExpressionRequest {
+    pub retina_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sparsity: Option<f64>,
+}
+
+impl Default for GetExpressionRequest {
+    fn default() -> Self {
+        Self {
+            retina_name: "en_general".to_string(),
+            sparsity: None,
+        }
+    }
+}
+
+impl GetExpressionRequest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_retina_name(mut self, retina_name: &str) -> Self {
+        self.retina_name = retina_name.to_string();
+        self
+    }
+
+    pub fn with_sparsity(mut self, sparsity: f64) -> Self {
+        self.sparsity = Some(sparsity);
+        self
+    }
+}
+
+/// Query parameters of `/expressions/contexts`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GetExpressionContextsRequest {
+    pub retina_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_index: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_results: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sparsity: Option<f64>,
+    pub get_fingerprint: bool,
+}
+
+impl Default for GetExpressionContextsRequest {
+    fn default() -> Self {
+        Self {
+            retina_name: "en_general".to_string(),
+            start_index: None,
+            max_results: None,
+            sparsity: None,
+            get_fingerprint: false,
+        }
+    }
+}
+
+impl GetExpressionContextsRequest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_retina_name(mut self, retina_name: &str) -> Self {
+        self.retina_name = retina_name.to_string();
+        self
+    }
+
+    pub fn with_start_index(mut self, start_index: u32) -> Self {
+        self.start_index = Some(start_index);
+        self
+    }
+
+    pub fn with_max_results(mut self, max_results: u32) -> Self {
+        self.max_results = Some(max_results);
+        self
+    }
+
+    pub fn with_sparsity(mut self, sparsity: f64) -> Self {
+        self.sparsity = Some(sparsity);
+        self
+    }
+
+    pub fn with_get_fingerprint(mut self, get_fingerprint: bool) -> Self {
+        self.get_fingerprint = get_fingerprint;
+        self
+    }
+}
+
+/// Query parameters of `/expressions/similar_terms`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GetExpressionSimilarTermsRequest {
+    pub retina_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context_id: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pos_type: Option<PosType>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_index: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_results: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sparsity: Option<f64>,
+    pub get_fingerprint: bool,
+}
+
+impl Default for GetExpressionSimilarTermsRequest {
+    fn default() -> Self {
+        Self {
+            retina_name: "en_general".to_string(),
+            context_id: None,
+            pos_type: None,
+            start_index: None,
+            max_results: None,
+            sparsity: None,
+            get_fingerprint: false,
+        }
+    }
+}
+
+impl GetExpressionSimilarTermsRequest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_retina_name(mut self, retina_name: &str) -> Self {
+        self.retina_name = retina_name.to_string();
+        self
+    }
+
+    pub fn with_context_id(mut self, context_id: u32) -> Self {
+        self.context_id = Some(context_id);
+        self
+    }
+
+    pub fn with_pos_type(mut self, pos_type: PosType) -> Self {
+        self.pos_type = Some(pos_type);
+        self
+    }
+
+    pub fn with_start_index(mut self, start_index: u32) -> Self {
+        self.start_index = Some(start_index);
+        self
+    }
+
+    pub fn with_max_results(mut self, max_results: u32) -> Self {
+        self.max_results = Some(max_results);
+        self
+    }
+
+    pub fn with_sparsity(mut self, sparsity: f64) -> Self {
+        self.sparsity = Some(sparsity);
+        self
+    }
+
+    pub fn with_get_fingerprint(mut self, get_fingerprint: bool) -> Self {
+        self.get_fingerprint = get_fingerprint;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::Fingerprint;
+
+    use super::Expression;
+
+    #[test]
+    fn serializes_nested_expressions() {
+        let expression =
+            Expression::sub([
+                Expression::term("jaguar"),
+                Expression::or([
+                    Expression::term("car"),
+                    Expression::text("a fast sports car"),
+                    Fingerprint { positions: vec![1, 2, 3] }.into(),
+                ]),
+            ]);
+
+        let value = serde_json::to_value(&expression).unwrap();
+
+        assert_eq!(
+            value,
+            json!({
+                "sub": [
+                    { "term": "jaguar" },
+                    { "or": [
+                        { "term": "car" },
+                        { "text": "a fast sports car" },
+                        { "positions": [1, 2, 3] }
+                    ] }
+                ]
+            })
+        );
+
+        assert_eq!(serde_json::from_value::<Expression>(value).unwrap(), expression);
+    }
+}
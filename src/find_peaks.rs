@@ -303,7 +303,6 @@ impl<'a, T, S> PeakFinder<'a, T, S>
 
             peaks = peaks[1..]
                 .iter()
-                .cloned()
                 .filter(|p| {
                     let x = x_data[p.middle_position()].clone();
 
@@ -316,6 +315,7 @@ impl<'a, T, S> PeakFinder<'a, T, S>
 
                     limit.is_inside(&dist)
                 })
+                .cloned()
                 .collect();
         }
         filtered.extend(peaks);
@@ -347,7 +347,7 @@ impl<'a, T, S> PeakFinder<'a, T, S>
             (None, None) => self.zero.clone().unwrap(),
             (Some(v), None) => peak_height - v.clone(),
             (None, Some(v)) => peak_height - v.clone(),
-            (Some(v1), Some(v2)) => peak_height - (if v1.ge(&v2) { v1 } else { v2 }).clone(),
+            (Some(v1), Some(v2)) => peak_height - (if v1.ge(v2) { v1 } else { v2 }).clone(),
         }
             .clone()
     }
@@ -364,7 +364,7 @@ impl<'a, T, S> PeakFinder<'a, T, S>
     /// # Examples
     ///
     /// ```
-    /// use find_peaks::PeakFinder;
+    /// use cortical_io::find_peaks::PeakFinder;
     /// let y = [1., 2., 3., 0., 5., 0.];
     ///
     /// let ps = PeakFinder::new(&y)
@@ -0,0 +1,324 @@
+use std::ops::{Index, IndexMut};
+
+use crate::{Error, Fingerprint, RetinaShape};
+
+/// One value per position of a retina, stored row by row.
+///
+/// Cells are addressed as `(x, y)`, where `x` is the column and `y` the row, so position `p` sits
+/// at `(p % width, p / width)`. Density estimation, peak finding, imaging and the spatial metrics
+/// all go through this type rather than doing the arithmetic themselves.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Grid<T> {
+    shape: RetinaShape,
+    data: Vec<T>,
+}
+
+impl<T> Grid<T> {
+    /// Takes one value per position of `shape`, row by row.
+    pub fn new(shape: RetinaShape, data: Vec<T>) -> Result<Self, Error> {
+        shape.check_len(data.len())?;
+
+        Ok(
+            Self {
+                shape,
+                data,
+            }
+        )
+    }
+
+    pub fn filled(shape: RetinaShape, value: T) -> Self
+        where
+            T: Clone,
+    {
+        Self {
+            shape,
+            data: vec![value; shape.len()],
+        }
+    }
+
+    /// Calls `f` with the `(x, y)` of every cell, row by row.
+    pub fn from_fn(shape: RetinaShape, mut f: impl FnMut(usize, usize) -> T) -> Self {
+        Self {
+            shape,
+            data:
+                (0..shape.len())
+                    .map(|i| {
+                        let (x, y) = shape.coordinates(i);
+
+                        f(x, y)
+                    })
+                    .collect(),
+        }
+    }
+
+    /// `on` at the positions of `fingerprint`, `off` everywhere else.
+    pub fn from_fingerprint(fingerprint: &Fingerprint, shape: RetinaShape, off: T, on: T) -> Result<Self, Error>
+        where
+            T: Clone,
+    {
+        let mut grid = Self::filled(shape, off);
+
+        for position in fingerprint.positions.iter() {
+            match grid.data.get_mut(*position as usize) {
+                Some(value) => *value = on.clone(),
+                None => return Err(Error::PositionOutOfRange { position: *position, len: shape.len() }),
+            }
+        }
+
+        Ok(grid)
+    }
+
+    pub fn shape(&self) -> RetinaShape {
+        self.shape
+    }
+
+    /// The number of columns.
+    pub fn width(&self) -> usize {
+        self.shape.columns as usize
+    }
+
+    /// The number of rows.
+    pub fn height(&self) -> usize {
+        self.shape.rows as usize
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// The position of cell `(x, y)`.
+    pub fn position(&self, x: usize, y: usize) -> usize {
+        self.shape.position(x, y)
+    }
+
+    /// The `(x, y)` of `position`.
+    pub fn coordinates(&self, position: usize) -> (usize, usize) {
+        self.shape.coordinates(position)
+    }
+
+    /// The value of cell `(x, y)`, or `None` outside the grid.
+    pub fn get(&self, x: usize, y: usize) -> Option<&T> {
+        if x < self.width() && y < self.height() {
+            self.data.get(self.position(x, y))
+        } else {
+            None
+        }
+    }
+
+    pub fn get_mut(&mut self, x: usize, y: usize) -> Option<&mut T> {
+        if x < self.width() && y < self.height() {
+            let position = self.position(x, y);
+
+            self.data.get_mut(position)
+        } else {
+            None
+        }
+    }
+
+    /// The values in position order.
+    pub fn as_slice(&self) -> &[T] {
+        &self.data
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        &mut self.data
+    }
+
+    pub fn into_vec(self) -> Vec<T> {
+        self.data
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, T> {
+        self.data.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, T> {
+        self.data.iter_mut()
+    }
+
+    /// Every cell with its `(x, y)`, row by row.
+    pub fn cells(&self) -> impl Iterator<Item = ((usize, usize), &T)> {
+        self.data
+            .iter()
+            .enumerate()
+            .map(move |(i, value)| (self.coordinates(i), value))
+    }
+
+    /// The cells at most `radius` columns and rows away from `(x, y)`, `(x, y)` included, clipped
+    /// to the grid.
+    pub fn window(&self, x: usize, y: usize, radius: usize) -> impl Iterator<Item = ((usize, usize), &T)> {
+        let columns = x.saturating_sub(radius)..x.saturating_add(radius + 1).min(self.width());
+        let rows = y.saturating_sub(radius)..y.saturating_add(radius + 1).min(self.height());
+
+        rows.flat_map(move |y| columns.clone().map(move |x| ((x, y), &self[(x, y)])))
+    }
+
+    /// The cells of row `y`, left to right. Panics outside the grid.
+    pub fn row(&self, y: usize) -> &[T] {
+        assert!(y < self.height(), "row {} out of bounds for height {}", y, self.height());
+
+        &self.data[y * self.width()..(y + 1) * self.width()]
+    }
+
+    /// The rows from top to bottom.
+    pub fn rows(&self) -> impl Iterator<Item = &[T]> {
+        (0..self.height()).map(move |y| self.row(y))
+    }
+
+    /// The cells of column `x`, top to bottom. Panics outside the grid.
+    pub fn column(&self, x: usize) -> impl Iterator<Item = &T> {
+        assert!(x < self.width(), "column {} out of bounds for width {}", x, self.width());
+
+        self.data[x..]
+            .iter()
+            .step_by(self.width())
+    }
+
+    /// The columns from left to right.
+    pub fn columns(&self) -> impl Iterator<Item = impl Iterator<Item = &T>> {
+        (0..self.width()).map(move |x| self.column(x))
+    }
+
+    pub fn map<U>(&self, f: impl FnMut(&T) -> U) -> Grid<U> {
+        Grid {
+            shape: self.shape,
+            data: self.data.iter().map(f).collect(),
+        }
+    }
+
+    /// Combines the cells of two grids of the same shape, failing with [`Error::RetinaMismatch`]
+    /// otherwise.
+    pub fn zip<U, V>(&self, other: &Grid<U>, mut f: impl FnMut(&T, &U) -> V) -> Result<Grid<V>, Error> {
+        if self.shape != other.shape {
+            return Err(Error::RetinaMismatch { expected: self.shape, actual: other.shape });
+        }
+
+        Ok(
+            Grid {
+                shape: self.shape,
+                data: self.data.iter().zip(other.data.iter()).map(|(l, r)| f(l, r)).collect(),
+            }
+        )
+    }
+
+    /// The positions whose value passes `active`.
+    pub fn to_fingerprint(&self, mut active: impl FnMut(&T) -> bool) -> Fingerprint {
+        Fingerprint {
+            positions:
+                self.data
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, value)| active(value))
+                    .map(|(position, _)| position as u32)
+                    .collect(),
+        }
+    }
+}
+
+/// Panics outside the grid.
+impl<T> Index<(usize, usize)> for Grid<T> {
+    type Output = T;
+
+    fn index(&self, (x, y): (usize, usize)) -> &T {
+        assert!(x < self.width() && y < self.height(), "cell ({}, {}) out of bounds for {}x{}", x, y, self.width(), self.height());
+
+        &self.data[self.position(x, y)]
+    }
+}
+
+impl<T> IndexMut<(usize, usize)> for Grid<T> {
+    fn index_mut(&mut self, (x, y): (usize, usize)) -> &mut T {
+        assert!(x < self.width() && y < self.height(), "cell ({}, {}) out of bounds for {}x{}", x, y, self.width(), self.height());
+
+        let position = self.position(x, y);
+
+        &mut self.data[position]
+    }
+}
+
+/// Lays the fingerprint out on the default 128x128 retina.
+impl TryFrom<&Fingerprint> for Grid<bool> {
+    type Error = Error;
+
+    fn try_from(fingerprint: &Fingerprint) -> Result<Self, Error> {
+        Grid::from_fingerprint(fingerprint, RetinaShape::default(), false, true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Error, Fingerprint, RetinaShape};
+
+    use super::Grid;
+
+    #[test]
+    fn indexes_by_column_and_row() {
+        // 2 rows of 3 columns
+        let grid = Grid::new(RetinaShape::new(2, 3), vec![0, 1, 2, 3, 4, 5]).unwrap();
+
+        assert_eq!((grid.width(), grid.height()), (3, 2));
+        assert_eq!(grid[(2, 0)], 2);
+        assert_eq!(grid[(0, 1)], 3);
+        assert_eq!(grid.get(3, 0), None);
+        assert_eq!(grid.get(0, 2), None);
+        assert_eq!(grid.coordinates(5), (2, 1));
+        assert_eq!(grid.position(2, 1), 5);
+
+        assert_eq!(grid.rows().collect::<Vec<_>>(), vec![&[0, 1, 2][..], &[3, 4, 5][..]]);
+        assert_eq!(
+            grid.columns().map(|column| column.copied().collect::<Vec<_>>()).collect::<Vec<_>>(),
+            vec![vec![0, 3], vec![1, 4], vec![2, 5]]
+        );
+        assert_eq!(grid, Grid::from_fn(RetinaShape::new(2, 3), |x, y| y * 3 + x));
+
+        assert!(matches!(Grid::new(RetinaShape::new(2, 3), vec![0; 5]), Err(Error::ShapeMismatch { expected: 6, actual: 5 })));
+    }
+
+    #[test]
+    #[should_panic(expected = "row 2 out of bounds for height 2")]
+    fn rows_outside_the_grid_panic() {
+        Grid::filled(RetinaShape::new(2, 3), 0).row(2);
+    }
+
+    #[test]
+    fn windows_clip_to_the_grid() {
+        let grid = Grid::from_fn(RetinaShape::new(3, 4), |x, y| (x, y));
+
+        let corner = grid.window(0, 0, 1).map(|(cell, _)| cell).collect::<Vec<_>>();
+
+        assert_eq!(corner, vec![(0, 0), (1, 0), (0, 1), (1, 1)]);
+        assert!(grid.window(2, 1, 1).all(|(cell, value)| cell == *value));
+        assert_eq!(grid.window(2, 1, 5).count(), 12);
+    }
+
+    #[test]
+    fn map_zip_and_fingerprints() {
+        let shape = RetinaShape::new(2, 3);
+        let fingerprint = Fingerprint { positions: vec![1, 5] };
+
+        let grid = Grid::from_fingerprint(&fingerprint, shape, 0u32, 1).unwrap();
+
+        assert_eq!(grid.as_slice(), &[0, 1, 0, 0, 0, 1]);
+        assert_eq!(grid[(2, 1)], 1);
+        assert_eq!(grid.to_fingerprint(|value| *value > 0), fingerprint);
+
+        let doubled = grid.map(|value| value * 2);
+        let sum = grid.zip(&doubled, |l, r| l + r).unwrap();
+
+        assert_eq!(sum.into_vec(), vec![0, 3, 0, 0, 0, 3]);
+        assert!(matches!(
+            grid.zip(&Grid::filled(RetinaShape::new(3, 2), 0), |l, r| l + r),
+            Err(Error::RetinaMismatch { expected, actual }) if expected == shape && actual == RetinaShape::new(3, 2)
+        ));
+
+        assert!(matches!(
+            Grid::from_fingerprint(&fingerprint, RetinaShape::new(1, 5), 0, 1),
+            Err(Error::PositionOutOfRange { position: 5, len: 5 })
+        ));
+        assert_eq!(Grid::<bool>::try_from(&fingerprint).unwrap().len(), 16384);
+    }
+}
@@ -2,71 +2,58 @@ use std::ops::{Div, Mul};
 
 use image;
 use image::ImageBuffer;
-use num::Integer;
-use num_traits::cast::FromPrimitive;
 use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator};
 
-use crate::Fingerprint;
-
-const IMAGE_WIDTH: u32 = 128;
-const IMAGE_HEIGHT: u32 = 128;
+use crate::{Error, Fingerprint, Grid, RetinaShape};
 
+/// Draws one `scale` x `scale` block per position of `shape`.
 pub fn generate_image_from_fingerprint(
     fingerprint: &Fingerprint,
+    shape: RetinaShape,
     scale: u32,
-) -> Option<image::DynamicImage> {
-    let fp_vec = fingerprint.expand((IMAGE_WIDTH * IMAGE_HEIGHT) as usize);
+) -> Result<image::DynamicImage, Error> {
+    let grid = Grid::from_fingerprint(fingerprint, shape, 0u8, 1u8)?;
 
-    generate_image_from_vec(&fp_vec, scale)
+    Ok(generate_image_from_grid(&grid, scale))
 }
 
+/// Scales a grid up by `scale` in both directions. Also returns, for every scaled cell, the
+/// position of the cell it was scaled from.
 #[inline(always)]
-pub fn visual_rescale_vec_by<T: Integer + Copy, P: Integer + Copy>(
-    fp_vec: &[T],
+pub fn visual_rescale_by<T: Copy, P>(
+    grid: &Grid<T>,
     scale: u32,
     fn_scale: impl Fn(T) -> P,
-) -> (Vec<P>, Vec<usize>) {
-    let mut new_vec: Vec<P> =
-        vec![P::zero(); (fp_vec.len() * scale as usize) * scale as usize];
-
-    let mut ref_vec: Vec<usize> =
-        vec![0usize; (fp_vec.len() * scale as usize) * scale as usize];
+) -> (Grid<P>, Grid<usize>) {
+    let scale = scale as usize;
+    let scaled_shape = RetinaShape::new(grid.shape().rows * scale as u32, grid.shape().columns * scale as u32);
 
     // when scaling a pixel by 2, make all 4 pixels the same value
-    for (i, point) in fp_vec.iter().enumerate() {
-        let x = i % IMAGE_WIDTH as usize;
-        let y = i / IMAGE_WIDTH as usize;
-
-        let x_start = x * scale as usize;
-        let y_start = y * scale as usize;
-
-        for x in x_start..x_start + scale as usize {
-            for y in y_start..y_start + scale as usize {
-                let index = (y * (IMAGE_WIDTH * scale) as usize) + x;
-                new_vec[index] = fn_scale(*point);
-                ref_vec[index] = i;
-            }
-        }
-    }
+    let ref_grid =
+        Grid::from_fn(scaled_shape, |x, y| grid.position(x / scale, y / scale));
 
-    (new_vec, ref_vec)
+    let scaled_grid =
+        ref_grid.map(|position| fn_scale(grid.as_slice()[*position]));
+
+    (scaled_grid, ref_grid)
 }
 
-pub fn generate_image_from_vec(
-    fp_vec: &[u8],
+pub fn generate_image_from_grid(
+    grid: &Grid<u8>,
     scale: u32,
-) -> Option<image::DynamicImage> {
-    let (scaled_fp_vec, _) =
-        visual_rescale_vec_by::<u8, u8>(
-            fp_vec,
+) -> image::DynamicImage {
+    let (scaled_grid, _) =
+        visual_rescale_by::<u8, u8>(
+            grid,
             scale,
             |p| p,
         );
 
     let buf = ImageBuffer::from_raw(
-        IMAGE_WIDTH * scale,
-        IMAGE_HEIGHT * scale,
-        scaled_fp_vec
+        scaled_grid.width() as u32,
+        scaled_grid.height() as u32,
+        scaled_grid
+            .into_vec()
             .into_par_iter()
             .map(|point| {
                 if point == 0 {
@@ -77,41 +64,89 @@ pub fn generate_image_from_vec(
             })
             .flatten()
             .collect::<Vec<u8>>(),
-    )?;
+    )
+        .expect("the buffer covers the scaled grid");
 
-    Some(image::DynamicImage::ImageRgb8(buf))
+    image::DynamicImage::ImageRgb8(buf)
 }
 
-pub fn generate_height_image_from_vec(
-    fp_vec: &[u32],
+/// Shades every cell by its value relative to the largest one, 0..=255. `fn_color` also gets the
+/// position of the unscaled cell.
+pub fn generate_height_image_from_grid(
+    grid: &Grid<u32>,
     scale: u32,
     fn_color: impl Fn(u8, usize) -> [u8; 3] + Sync,
-) -> Option<image::DynamicImage> {
-    let fp_max = fp_vec.iter().max()?;
+) -> image::DynamicImage {
+    let fp_max = grid.iter().max().copied().unwrap_or_default();
 
-    let (scaled_fp_vec, ref_vec) =
-        visual_rescale_vec_by::<u32, u8>(
-            fp_vec,
+    let (scaled_grid, ref_grid) =
+        visual_rescale_by::<u32, u8>(
+            grid,
             scale,
             |p|
                 p
                     .mul(255)
-                    .div(*fp_max.max(&1))
+                    .div(fp_max.max(1))
                     .min(255) as u8,
         );
 
     let buf = ImageBuffer::from_raw(
-        IMAGE_WIDTH * scale,
-        IMAGE_HEIGHT * scale,
-        scaled_fp_vec
+        scaled_grid.width() as u32,
+        scaled_grid.height() as u32,
+        scaled_grid
+            .into_vec()
             .into_par_iter()
-            .enumerate()
-            .map(|(i, point)| {
-                fn_color(point, ref_vec[i])
+            .zip(ref_grid.into_vec())
+            .map(|(point, position)| {
+                fn_color(point, position)
             })
             .flatten()
             .collect::<Vec<u8>>(),
-    )?;
+    )
+        .expect("the buffer covers the scaled grid");
+
+    image::DynamicImage::ImageRgb8(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::GenericImageView;
+
+    use crate::{Error, Fingerprint, Grid, RetinaShape};
+
+    use super::{generate_height_image_from_grid, generate_image_from_fingerprint, visual_rescale_by};
+
+    #[test]
+    fn image_follows_the_retina_shape() {
+        let shape = RetinaShape::new(2, 3);
+        let fingerprint = Fingerprint { positions: vec![5] };
 
-    Some(image::DynamicImage::ImageRgb8(buf))
-}
\ No newline at end of file
+        let image = generate_image_from_fingerprint(&fingerprint, shape, 2).unwrap();
+
+        assert_eq!(image.dimensions(), (6, 4));
+        assert_eq!(image.get_pixel(5, 3).0, [0, 0, 0, 255]);
+        assert_eq!(image.get_pixel(3, 3).0, [255, 255, 255, 255]);
+
+        assert!(matches!(
+            generate_image_from_fingerprint(&Fingerprint { positions: vec![6] }, shape, 2),
+            Err(Error::PositionOutOfRange { position: 6, len: 6 })
+        ));
+    }
+
+    #[test]
+    fn rescales_grids() {
+        let grid = Grid::new(RetinaShape::new(2, 3), vec![0u32, 1, 2, 3, 4, 5]).unwrap();
+
+        let (scaled, positions) = visual_rescale_by(&grid, 2, |p| p * 10);
+
+        assert_eq!((scaled.width(), scaled.height()), (6, 4));
+        assert_eq!(scaled[(5, 3)], 50);
+        assert_eq!(scaled[(2, 1)], 10);
+        assert_eq!(positions[(5, 0)], 2);
+
+        let image = generate_height_image_from_grid(&grid, 1, |p, position| [p, position as u8, 0]);
+
+        assert_eq!(image.get_pixel(2, 1).0, [255, 5, 0, 255]);
+        assert_eq!(image.get_pixel(1, 0).0, [51, 1, 0, 255]);
+    }
+}
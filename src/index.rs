@@ -0,0 +1,555 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+use crate::metric::MetricKind;
+use crate::pairwise::{best_k, Neighbour};
+use crate::similarity::{sorted_overlap, sorted_positions, FingerprintSimilarity};
+use crate::{Error, Fingerprint, Grid, RetinaShape, SimilarityMetric};
+
+/// An in-memory search index over fingerprints, keyed by an id of the caller's choosing.
+///
+/// Every retina position keeps a posting list of the fingerprints that have it set. A query only
+/// walks the posting lists of its own positions, which yields the exact overlap with every
+/// fingerprint it shares a position with; any [`SimilarityMetric`] is then computed from those
+/// counts. Fingerprints that share no position with the query are never scored.
+///
+/// Only metrics with a [`SimilarityMetric::radius`] can be searched this way; the others, such as
+/// [`Hamming`](crate::metric::Hamming), may rank a fingerprint sharing no position above one that
+/// does and are rejected. For a spatial metric like
+/// [`RadiusOverlap`](crate::spatial::RadiusOverlap), the query is first widened by its radius, so
+/// fingerprints with a position near one of the query's are found too.
+///
+/// Deleting only marks a fingerprint as gone. The posting lists are rebuilt once deleted entries
+/// outnumber live ones, or on [`FingerprintIndex::compact`].
+#[derive(Debug, Clone)]
+pub struct FingerprintIndex<K> {
+    shape: RetinaShape,
+    postings: Vec<Vec<u32>>,
+    entries: Vec<Option<Entry<K>>>,
+    slots: HashMap<K, u32>,
+    deleted: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Entry<K> {
+    id: K,
+    positions: Vec<u32>,
+}
+
+/// A search result.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hit<K> {
+    pub id: K,
+    pub score: f64,
+    /// The number of positions shared with the query.
+    pub overlap: u32,
+}
+
+/// How many results a search returns and how many candidates it scores.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SearchOptions {
+    pub k: usize,
+    /// Ignores fingerprints sharing fewer positions with the query, or for a spatial metric,
+    /// having fewer positions within its radius of the query.
+    pub min_overlap: u32,
+    /// Scores only this many candidates with the largest overlap. Makes the search approximate
+    /// for metrics that are not monotonic in the overlap.
+    pub max_candidates: Option<usize>,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            k: 10,
+            min_overlap: 1,
+            max_candidates: None,
+        }
+    }
+}
+
+impl SearchOptions {
+    pub fn new(k: usize) -> Self {
+        Self {
+            k,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_min_overlap(mut self, min_overlap: u32) -> Self {
+        self.min_overlap = min_overlap.max(1);
+        self
+    }
+
+    pub fn with_max_candidates(mut self, max_candidates: usize) -> Self {
+        self.max_candidates = Some(max_candidates);
+        self
+    }
+}
+
+impl<K: Clone + Eq + Hash> Default for FingerprintIndex<K> {
+    fn default() -> Self {
+        Self::with_shape(RetinaShape::default())
+    }
+}
+
+impl<K: Clone + Eq + Hash> FingerprintIndex<K> {
+    /// An empty index for fingerprints of the default 128x128 retina.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_shape(shape: RetinaShape) -> Self {
+        Self {
+            shape,
+            postings: vec![Vec::new(); shape.len()],
+            entries: Vec::new(),
+            slots: HashMap::new(),
+            deleted: 0,
+        }
+    }
+
+    pub fn shape(&self) -> RetinaShape {
+        self.shape
+    }
+
+    /// The number of fingerprints in the index.
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    pub fn contains(&self, id: &K) -> bool {
+        self.slots.contains_key(id)
+    }
+
+    pub fn get(&self, id: &K) -> Option<Fingerprint> {
+        let entry = self.entries[*self.slots.get(id)? as usize].as_ref()?;
+
+        Some(Fingerprint { positions: entry.positions.clone() })
+    }
+
+    /// Adds a fingerprint, replacing the one stored under `id` if there is one.
+    pub fn insert(&mut self, id: K, fingerprint: &Fingerprint) -> Result<(), Error> {
+        let positions = sorted_positions(fingerprint, self.shape)?;
+
+        if self.entries.len() >= u32::MAX as usize {
+            self.compact();
+        }
+
+        self.delete(&id);
+
+        let slot = self.entries.len() as u32;
+
+        for position in positions.iter() {
+            self.postings[*position as usize].push(slot);
+        }
+
+        self.slots.insert(id.clone(), slot);
+        self.entries.push(Some(Entry { id, positions }));
+
+        Ok(())
+    }
+
+    /// Removes the fingerprint stored under `id`, returning whether there was one.
+    pub fn delete(&mut self, id: &K) -> bool {
+        let slot =
+            match self.slots.remove(id) {
+                Some(slot) => slot,
+                None => return false,
+            };
+
+        self.entries[slot as usize] = None;
+        self.deleted += 1;
+
+        if self.deleted > self.slots.len() {
+            self.compact();
+        }
+
+        true
+    }
+
+    /// Drops deleted fingerprints from the posting lists.
+    pub fn compact(&mut self) {
+        if self.deleted == 0 {
+            return;
+        }
+
+        self.entries.retain(Option::is_some);
+        self.postings.iter_mut().for_each(Vec::clear);
+        self.slots.clear();
+
+        for (slot, entry) in self.entries.iter().enumerate() {
+            let entry = entry.as_ref().expect("deleted entries were dropped");
+
+            for position in entry.positions.iter() {
+                self.postings[*position as usize].push(slot as u32);
+            }
+
+            self.slots.insert(entry.id.clone(), slot as u32);
+        }
+
+        self.deleted = 0;
+    }
+
+    /// The `options.k` fingerprints that score best against `query`, best first. Only fingerprints
+    /// with a position within the metric's radius of one of the query's are candidates.
+    pub fn search(
+        &self,
+        query: &Fingerprint,
+        options: SearchOptions,
+        metric: &dyn SimilarityMetric,
+    ) -> Result<Vec<Hit<K>>, Error> {
+        let radius = searchable_radius(metric)?;
+        let query = sorted_positions(query, self.shape)?;
+
+        Ok(self.search_positions(&query, radius, options, metric))
+    }
+
+    fn search_positions(&self, query: &[u32], radius: u32, options: SearchOptions, metric: &dyn SimilarityMetric) -> Vec<Hit<K>> {
+        let widened =
+            if radius == 0 {
+                None
+            } else {
+                Some(self.widen(query, radius as usize))
+            };
+
+        // keyed by slot, so a query costs the length of its posting lists and not the index size
+        let mut overlaps = HashMap::<u32, u32>::new();
+
+        for position in widened.as_deref().unwrap_or(query).iter() {
+            for slot in self.postings[*position as usize].iter() {
+                *overlaps.entry(*slot).or_insert(0) += 1;
+            }
+        }
+
+        let candidates =
+            overlaps
+                .iter()
+                .filter(|(slot, _)| self.entries[**slot as usize].is_some())
+                .map(|(slot, overlap)| Neighbour { index: *slot as usize, score: *overlap as f64 })
+                .filter(|candidate| candidate.score >= options.min_overlap.max(1) as f64);
+
+        let candidates =
+            match options.max_candidates {
+                Some(max_candidates) => best_k(candidates, max_candidates, MetricKind::Similarity),
+                None => candidates.collect(),
+            };
+
+        let scored =
+            candidates
+                .iter()
+                .map(|candidate| {
+                    let entry = self.entry(candidate.index);
+
+                    if metric.is_spatial() {
+                        return Neighbour { index: candidate.index, score: metric.score_positions(query, &entry.positions, self.shape) };
+                    }
+
+                    let counts =
+                        FingerprintSimilarity::from_counts(
+                            query.len() as u32,
+                            entry.positions.len() as u32,
+                            candidate.score as u32,
+                            self.shape.len(),
+                        );
+
+                    let score =
+                        metric
+                            .score_counts(&counts)
+                            .unwrap_or_else(|| metric.score_positions(query, &entry.positions, self.shape));
+
+                    Neighbour { index: candidate.index, score }
+                });
+
+        best_k(scored, options.k, metric.kind())
+            .into_iter()
+            .map(|neighbour| {
+                let entry = self.entry(neighbour.index);
+
+                Hit {
+                    id: entry.id.clone(),
+                    score: neighbour.score,
+                    overlap:
+                        match widened {
+                            Some(_) => sorted_overlap(query, &entry.positions),
+                            None => overlaps[&(neighbour.index as u32)],
+                        },
+                }
+            })
+            .collect()
+    }
+
+    /// The positions at most `radius` rows and columns away from a position of `query`, sorted.
+    fn widen(&self, query: &[u32], radius: usize) -> Vec<u32> {
+        let mut widened = Grid::filled(self.shape, false);
+
+        for position in query.iter() {
+            let (x, y) = widened.coordinates(*position as usize);
+
+            let cells =
+                widened
+                    .window(x, y, radius)
+                    .map(|(cell, _)| cell)
+                    .collect::<Vec<_>>();
+
+            for cell in cells {
+                widened[cell] = true;
+            }
+        }
+
+        widened
+            .to_fingerprint(|active| *active)
+            .positions
+    }
+
+    fn entry(&self, slot: usize) -> &Entry<K> {
+        self.entries[slot]
+            .as_ref()
+            .expect("candidates are live entries")
+    }
+}
+
+impl<K: Clone + Eq + Hash + Send + Sync> FingerprintIndex<K> {
+    /// Runs [`FingerprintIndex::search`] for every query, in parallel with the `parallel` feature.
+    pub fn search_batch(
+        &self,
+        queries: &[Fingerprint],
+        options: SearchOptions,
+        metric: &dyn SimilarityMetric,
+    ) -> Result<Vec<Vec<Hit<K>>>, Error> {
+        let radius = searchable_radius(metric)?;
+
+        let queries =
+            queries
+                .iter()
+                .map(|query| sorted_positions(query, self.shape))
+                .collect::<Result<Vec<_>, _>>()?;
+
+        #[cfg(feature = "parallel")]
+        let hits =
+            queries
+                .par_iter()
+                .map(|query| self.search_positions(query, radius, options, metric))
+                .collect();
+
+        #[cfg(not(feature = "parallel"))]
+        let hits =
+            queries
+                .iter()
+                .map(|query| self.search_positions(query, radius, options, metric))
+                .collect();
+
+        Ok(hits)
+    }
+}
+
+/// The radius to widen queries by for `metric`.
+fn searchable_radius(metric: &dyn SimilarityMetric) -> Result<u32, Error> {
+    metric
+        .radius()
+        .ok_or_else(|| Error::UnsupportedMetric {
+            metric: metric.name().to_string(),
+            reason: "fingerprints sharing no position with the query can score better than ones that do, so an inverted index can't find every candidate",
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::collection::{btree_set, vec};
+    use proptest::prelude::*;
+
+    use crate::metric::{Euclidean, Hamming, Jaccard, JaccardDistance, Overlap};
+    use crate::pairwise::top_k;
+    use crate::spatial::{BlurredCosine, EarthMovers, RadiusOverlap};
+    use crate::{Error, Fingerprint, RetinaShape, SimilarityMetric};
+
+    use super::{FingerprintIndex, Hit, SearchOptions};
+
+    fn fingerprint(positions: &[u32]) -> Fingerprint {
+        Fingerprint { positions: positions.to_vec() }
+    }
+
+    fn index() -> FingerprintIndex<&'static str> {
+        let mut index = FingerprintIndex::new();
+
+        index.insert("a", &fingerprint(&[1, 2, 3, 4])).unwrap();
+        index.insert("b", &fingerprint(&[3, 4, 5, 6])).unwrap();
+        index.insert("c", &fingerprint(&[1, 2, 3, 4, 5])).unwrap();
+        index.insert("d", &fingerprint(&[100, 200])).unwrap();
+
+        index
+    }
+
+    fn ids(hits: &[Hit<&'static str>]) -> Vec<&'static str> {
+        hits.iter().map(|hit| hit.id).collect()
+    }
+
+    /// Whether a position of `a` is at most `radius` rows and columns away from one of `b`, on the
+    /// default retina.
+    fn within(a: &[u32], b: &[u32], radius: u32) -> bool {
+        let width = RetinaShape::default().columns;
+
+        a.iter().any(|a| {
+            b.iter().any(|b| (a % width).abs_diff(b % width) <= radius && (a / width).abs_diff(b / width) <= radius)
+        })
+    }
+
+    #[test]
+    fn searches_by_overlap() {
+        let index = index();
+
+        let hits = index.search(&fingerprint(&[1, 2, 3]), SearchOptions::new(10), &Jaccard).unwrap();
+
+        assert_eq!(
+            hits,
+            vec![
+                Hit { id: "a", score: 0.75, overlap: 3 },
+                Hit { id: "c", score: 0.6, overlap: 3 },
+                Hit { id: "b", score: 1.0 / 6.0, overlap: 1 },
+            ]
+        );
+
+        let pruned = index.search(&fingerprint(&[1, 2, 3]), SearchOptions::new(10).with_min_overlap(2), &Jaccard).unwrap();
+
+        assert_eq!(ids(&pruned), vec!["a", "c"]);
+
+        // the largest overlaps survive, then the metric decides
+        let capped = index.search(&fingerprint(&[3, 4, 5, 6]), SearchOptions::new(1).with_max_candidates(2), &JaccardDistance).unwrap();
+
+        assert_eq!(ids(&capped), vec!["b"]);
+    }
+
+    #[test]
+    fn insert_replace_and_delete() {
+        let mut index = index();
+
+        index.insert("a", &fingerprint(&[100, 200])).unwrap();
+
+        assert_eq!(index.len(), 4);
+        assert_eq!(index.get(&"a"), Some(fingerprint(&[100, 200])));
+
+        let hits = index.search(&fingerprint(&[100]), SearchOptions::new(10), &Overlap).unwrap();
+
+        assert_eq!(ids(&hits), vec!["d", "a"]);
+
+        assert!(index.delete(&"d"));
+        assert!(!index.delete(&"d"));
+        assert!(!index.contains(&"d"));
+
+        let hits = index.search(&fingerprint(&[100]), SearchOptions::new(10), &Overlap).unwrap();
+
+        assert_eq!(ids(&hits), vec!["a"]);
+
+        // more deleted entries than live ones compact the posting lists
+        index.delete(&"a");
+
+        assert_eq!(index.postings[3], vec![0, 1]);
+
+        index.delete(&"b");
+        index.compact();
+
+        assert_eq!(index.len(), 1);
+        assert_eq!(index.postings[3], vec![0]);
+        assert_eq!(ids(&index.search(&fingerprint(&[3]), SearchOptions::new(10), &Overlap).unwrap()), vec!["c"]);
+    }
+
+    #[test]
+    fn batch_search() {
+        let index = index();
+
+        let hits =
+            index
+                .search_batch(&[fingerprint(&[5, 6]), fingerprint(&[200]), fingerprint(&[9000])], SearchOptions::new(1), &Jaccard)
+                .unwrap();
+
+        assert_eq!(hits.iter().map(|hits| ids(hits)).collect::<Vec<_>>(), vec![vec!["b"], vec!["d"], vec![]]);
+    }
+
+    #[test]
+    fn spatial_search_finds_near_misses() {
+        let mut index = FingerprintIndex::with_shape(RetinaShape::new(8, 8));
+
+        // one cell right of the query, two cells right, and far away
+        index.insert("near", &fingerprint(&[10])).unwrap();
+        index.insert("farther", &fingerprint(&[11])).unwrap();
+        index.insert("far", &fingerprint(&[63])).unwrap();
+
+        let query = fingerprint(&[9]);
+
+        assert_eq!(ids(&index.search(&query, SearchOptions::new(10), &RadiusOverlap::new(1)).unwrap()), vec!["near"]);
+
+        let hits = index.search(&query, SearchOptions::new(10), &RadiusOverlap::new(2)).unwrap();
+
+        assert_eq!(hits, vec![Hit { id: "near", score: 1.0, overlap: 0 }, Hit { id: "farther", score: 1.0, overlap: 0 }]);
+
+        assert!(index.search(&query, SearchOptions::new(10), &Jaccard).unwrap().is_empty());
+
+        for metric in [&BlurredCosine::new(1.0) as &dyn SimilarityMetric, &EarthMovers, &Hamming, &Euclidean] {
+            assert!(matches!(index.search(&query, SearchOptions::new(10), metric), Err(Error::UnsupportedMetric { .. })));
+            assert!(matches!(index.search_batch(std::slice::from_ref(&query), SearchOptions::new(10), metric), Err(Error::UnsupportedMetric { .. })));
+        }
+    }
+
+    #[test]
+    fn rejects_positions_outside_the_retina() {
+        let mut index = FingerprintIndex::with_shape(RetinaShape::new(2, 2));
+
+        assert!(matches!(index.insert(1, &fingerprint(&[4])), Err(Error::PositionOutOfRange { position: 4, len: 4 })));
+        assert!(index.search(&fingerprint(&[7]), SearchOptions::new(1), &Jaccard).is_err());
+        assert!(index.is_empty());
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn matches_brute_force(
+            corpus in vec(btree_set(0u32..512, 0..40), 0..60),
+            query in btree_set(0u32..512, 1..40),
+            k in 1usize..10,
+        ) {
+            let corpus =
+                corpus
+                    .into_iter()
+                    .map(|positions| Fingerprint { positions: positions.into_iter().collect() })
+                    .collect::<Vec<_>>();
+
+            let query = Fingerprint { positions: query.into_iter().collect() };
+
+            let mut index = FingerprintIndex::new();
+
+            for (id, fingerprint) in corpus.iter().enumerate() {
+                index.insert(id, fingerprint).unwrap();
+            }
+
+            for (metric, radius) in [(&Jaccard as &dyn SimilarityMetric, 0), (&JaccardDistance, 0), (&RadiusOverlap::new(1), 1)] {
+                let hits = index.search(&query, SearchOptions::new(k), metric).unwrap();
+
+                // the rest of the corpus scores worse than every candidate, so it only fills up
+                // the brute force results when there are fewer than k candidates
+                let expected =
+                    top_k(&query, &corpus, corpus.len(), metric)
+                        .unwrap()
+                        .into_iter()
+                        .filter(|neighbour| within(&query.positions, &corpus[neighbour.index].positions, radius))
+                        .take(k)
+                        .map(|neighbour| (neighbour.index, neighbour.score))
+                        .collect::<Vec<_>>();
+
+                prop_assert_eq!(hits.into_iter().map(|hit| (hit.id, hit.score)).collect::<Vec<_>>(), expected, "{}", metric.name());
+            }
+
+            for metric in [&Hamming as &dyn SimilarityMetric, &Euclidean] {
+                let rejected = matches!(index.search(&query, SearchOptions::new(k), metric), Err(Error::UnsupportedMetric { .. }));
+
+                prop_assert!(rejected, "{}", metric.name());
+            }
+        }
+    }
+}
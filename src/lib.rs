@@ -2,19 +2,38 @@ extern crate core;
 
 use serde::{Deserialize, Serialize};
 
+pub use bitset::BitFingerprint;
 #[cfg(feature = "client")]
-pub use client::Cortical;
+pub use client::{Cortical, CorticalBuilder};
+pub use error::{ApiError, Error};
+pub use expression::Expression;
+pub use grid::Grid;
+pub use metric::SimilarityMetric;
 
-use crate::similarity::FingerprintSimilarity;
+use crate::similarity::{sorted_positions, FingerprintSimilarity, LocalCompare};
 
 pub mod similarity;
+pub mod spatial;
 
 #[cfg(feature = "image")]
 pub mod image;
+pub mod bitset;
+pub mod cluster;
 #[cfg(feature = "client")]
 pub mod client;
 pub mod density;
+pub mod error;
+pub mod expression;
 pub mod find_peaks;
+pub mod grid;
+pub mod index;
+pub mod metric;
+pub mod minhash;
+pub mod pairwise;
+#[cfg(feature = "client")]
+pub mod rate_limit;
+#[cfg(feature = "client")]
+pub mod retry;
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Retina {
@@ -29,12 +48,80 @@ pub struct Retina {
     pub number_of_rows: u32,
 }
 
+/// The grid a retina lays its positions out on. Position `p` sits in row `p / columns` and
+/// column `p % columns`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct RetinaShape {
+    pub rows: u32,
+    pub columns: u32,
+}
+
+impl Default for RetinaShape {
+    /// The 128x128 grid of the `en_general` retina.
+    fn default() -> Self {
+        Self {
+            rows: 128,
+            columns: 128,
+        }
+    }
+}
+
+impl RetinaShape {
+    pub fn new(rows: u32, columns: u32) -> Self {
+        Self {
+            rows,
+            columns,
+        }
+    }
+
+    /// The number of positions on the grid.
+    pub fn len(&self) -> usize {
+        self.rows as usize * self.columns as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains(&self, position: u32) -> bool {
+        (position as usize) < self.len()
+    }
+
+    /// The `(x, y)` of `position`: its column and its row, the order [`Grid`] indexes by.
+    pub fn coordinates(&self, position: usize) -> (usize, usize) {
+        (position % self.columns as usize, position / self.columns as usize)
+    }
+
+    /// The position of column `x` in row `y`.
+    pub fn position(&self, x: usize, y: usize) -> usize {
+        y * self.columns as usize + x
+    }
+
+    /// Fails with [`Error::ShapeMismatch`] unless `len` values cover the grid exactly.
+    pub fn check_len(&self, len: usize) -> Result<(), Error> {
+        if len != self.len() {
+            return Err(Error::ShapeMismatch { expected: self.len(), actual: len });
+        }
+
+        Ok(())
+    }
+}
+
+impl From<&Retina> for RetinaShape {
+    fn from(retina: &Retina) -> Self {
+        Self::new(retina.number_of_rows, retina.number_of_columns)
+    }
+}
+
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Fingerprint {
     pub positions: Vec<u32>,
 }
 
 impl Fingerprint {
+    /// One byte per position out of `len`, set to 1 where the fingerprint is active. Panics on a
+    /// position outside `len`.
+    #[deprecated(note = "use `expand_with_shape`, which reports positions outside the retina")]
     pub fn expand(&self, len: usize) -> Vec<u8> {
         let mut expanded = vec![0; len];
 
@@ -45,28 +132,123 @@ impl Fingerprint {
         expanded
     }
 
+    #[deprecated(note = "use `expand_t_with_shape`, which reports positions outside the retina")]
     pub fn expand_t<T: num::Float>(&self, len: usize) -> Vec<T> {
-        let zero = T::from(0).unwrap();
-        let one = T::from(1).unwrap();
-
-        let mut expanded: Vec<T> = vec![zero; len];
+        let mut expanded: Vec<T> = vec![T::zero(); len];
 
         for pos in self.positions.iter() {
-            expanded[*pos as usize] = one;
+            expanded[*pos as usize] = T::one();
         }
 
         expanded
     }
 
+    /// One byte per position of `shape`, set to 1 where the fingerprint is active.
+    pub fn expand_with_shape(&self, shape: RetinaShape) -> Result<Vec<u8>, Error> {
+        self.expand_t_with(shape, 0, 1)
+    }
+
+    pub fn expand_t_with_shape<T: num::Float>(&self, shape: RetinaShape) -> Result<Vec<T>, Error> {
+        self.expand_t_with(shape, T::zero(), T::one())
+    }
+
+    fn expand_t_with<T: Copy>(&self, shape: RetinaShape, zero: T, one: T) -> Result<Vec<T>, Error> {
+        Ok(
+            Grid::from_fingerprint(self, shape, zero, one)?
+                .into_vec()
+        )
+    }
+
+    /// Compares two fingerprints of the default 128x128 retina. Panics on a position outside it.
+    #[deprecated(note = "use `compare_with_shape`, which reports positions outside the retina")]
     pub fn compare(&self, other: &Fingerprint) -> FingerprintSimilarity {
-        FingerprintSimilarity::new(self, other)
+        self.compare_with_shape(other, RetinaShape::default())
+            .expect("fingerprint positions fit on the 128x128 retina")
+    }
+
+    pub fn compare_with_shape(&self, other: &Fingerprint, shape: RetinaShape) -> Result<FingerprintSimilarity, Error> {
+        FingerprintSimilarity::with_shape(self, other, shape)
+    }
+
+    /// Computes the `/compare` metrics that follow from the two position sets locally, without
+    /// calling the API, see [`LocalCompare`].
+    pub fn compare_full(&self, other: &Fingerprint) -> Result<LocalCompare, Error> {
+        Ok(
+            self.compare_with_shape(other, RetinaShape::default())?
+                .local_compare()
+        )
+    }
+
+    /// Summarizes fingerprints of the default 128x128 retina as one, see
+    /// [`Fingerprint::aggregate_with_shape`].
+    pub fn aggregate(fingerprints: &[Fingerprint], sparsity: f64) -> Result<Fingerprint, Error> {
+        Self::aggregate_with_shape(fingerprints, None, sparsity, RetinaShape::default())
+    }
+
+    pub fn aggregate_weighted(fingerprints: &[Fingerprint], weights: &[f64], sparsity: f64) -> Result<Fingerprint, Error> {
+        Self::aggregate_with_shape(fingerprints, Some(weights), sparsity, RetinaShape::default())
+    }
+
+    /// Every fingerprint votes for its positions, with its weight or 1, and the most voted
+    /// positions are kept until `sparsity` of the retina is active. Ties go to the lower position
+    /// and positions without votes are never kept, so the result may be sparser than asked for.
+    pub fn aggregate_with_shape(
+        fingerprints: &[Fingerprint],
+        weights: Option<&[f64]>,
+        sparsity: f64,
+        shape: RetinaShape,
+    ) -> Result<Fingerprint, Error> {
+        let len = target_len(sparsity, shape)?;
+
+        if let Some(weights) = weights {
+            if weights.len() != fingerprints.len() {
+                return Err(Error::Config(format!("expected {} weights, one per fingerprint, got {}", fingerprints.len(), weights.len())));
+            }
+
+            if let Some(weight) = weights.iter().find(|weight| !(weight.is_finite() && **weight >= 0.0)) {
+                return Err(Error::Config(format!("weights must be finite and non-negative, got {}", weight)));
+            }
+        }
+
+        let mut votes = vec![0.0f64; shape.len()];
+
+        for (i, fingerprint) in fingerprints.iter().enumerate() {
+            let weight = weights.map_or(1.0, |weights| weights[i]);
+
+            for position in sorted_positions(fingerprint, shape)? {
+                votes[position as usize] += weight;
+            }
+        }
+
+        let mut ranked =
+            (0..shape.len() as u32)
+                .filter(|position| votes[*position as usize] > 0.0)
+                .collect::<Vec<_>>();
+
+        ranked.sort_by(|a, b| votes[*b as usize].total_cmp(&votes[*a as usize]).then(a.cmp(b)));
+        ranked.truncate(len);
+        ranked.sort_unstable();
+
+        Ok(Fingerprint { positions: ranked })
     }
 }
 
-impl From<Fingerprint> for Vec<f64> {
-    fn from(fingerprint: Fingerprint) -> Self {
+/// The number of positions that fill `sparsity` of `shape`.
+pub(crate) fn target_len(sparsity: f64, shape: RetinaShape) -> Result<usize, Error> {
+    if !(0.0..=1.0).contains(&sparsity) {
+        return Err(Error::Config(format!("sparsity must be within 0..=1, got {}", sparsity)));
+    }
+
+    Ok((sparsity * shape.len() as f64).round() as usize)
+}
+
+/// Expands the fingerprint onto the default 128x128 retina.
+impl TryFrom<Fingerprint> for Vec<f64> {
+    type Error = Error;
+
+    fn try_from(fingerprint: Fingerprint) -> Result<Self, Error> {
         fingerprint
-            .expand_t::<f64>(128 * 128)
+            .expand_t_with_shape::<f64>(RetinaShape::default())
     }
 }
 
@@ -76,6 +258,8 @@ pub struct TextSlice {
     pub fingerprint: Option<Fingerprint>,
 }
 
+/// Query parameters of `/text/slices`. Like the other `*Request` query types, the fields keep
+/// their snake_case names because that is what the API expects in query strings.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TextSliceRequest {
     pub retina_name: String,
@@ -196,17 +380,129 @@ pub struct CompareResponse {
     pub cosine_similarity: f64,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum PlotShape {
+    #[serde(rename = "circle")]
+    Circle,
+    #[serde(rename = "square")]
+    Square,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ImageEncoding {
+    #[serde(rename = "base64/png")]
+    Base64Png,
+}
+
+/// Query parameters of `/image`, `/image/compare` and `/image/bulk`.
+///
+/// Without an `image_encoding` the API answers with raw PNG bytes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GetImageRequest {
+    pub retina_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_scalar: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub plot_shape: Option<PlotShape>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_encoding: Option<ImageEncoding>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sparsity: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub get_fingerprint: Option<bool>,
+}
+
+impl Default for GetImageRequest {
+    fn default() -> Self {
+        Self {
+            retina_name: "en_general".to_string(),
+            image_scalar: None,
+            plot_shape: None,
+            image_encoding: None,
+            sparsity: None,
+            get_fingerprint: None,
+        }
+    }
+}
+
+impl GetImageRequest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_retina_name(mut self, retina_name: &str) -> Self {
+        self.retina_name = retina_name.to_string();
+        self
+    }
+
+    pub fn with_image_scalar(mut self, image_scalar: u32) -> Self {
+        self.image_scalar = Some(image_scalar);
+        self
+    }
+
+    pub fn with_plot_shape(mut self, plot_shape: PlotShape) -> Self {
+        self.plot_shape = Some(plot_shape);
+        self
+    }
+
+    pub fn with_image_encoding(mut self, image_encoding: ImageEncoding) -> Self {
+        self.image_encoding = Some(image_encoding);
+        self
+    }
+
+    pub fn with_sparsity(mut self, sparsity: f64) -> Self {
+        self.sparsity = Some(sparsity);
+        self
+    }
+
+    /// Only used by `/image/bulk`.
+    pub fn with_get_fingerprint(mut self, get_fingerprint: bool) -> Self {
+        self.get_fingerprint = Some(get_fingerprint);
+        self
+    }
+}
+
+/// One entry of an `/image/bulk` response.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageData {
+    /// The base64 encoded PNG.
+    pub image_data: String,
+    pub fingerprint: Option<Fingerprint>,
+}
+
+impl ImageData {
+    /// Decodes the PNG bytes.
+    pub fn png(&self) -> Result<Vec<u8>, Error> {
+        decode_base64(&self.image_data)
+    }
+
+    #[cfg(feature = "image")]
+    pub fn to_image(&self) -> Result<::image::DynamicImage, Error> {
+        Ok(::image::load_from_memory(&self.png()?)?)
+    }
+}
+
+pub(crate) fn decode_base64(data: &str) -> Result<Vec<u8>, Error> {
+    use base64::Engine;
+
+    Ok(base64::engine::general_purpose::STANDARD.decode(data.trim())?)
+}
+
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct GetTermsRequest {
     pub retina_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub term: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub start_index: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub max_results: Option<u32>,
     pub get_fingerprint: bool,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Term {
+    #[serde(rename = "posTypes")]
     pub pos_types: Option<Vec<PosType>>,
     pub df: Option<f64>,
     pub score: Option<f64>,
@@ -220,7 +516,9 @@ pub type GetTermsResponse = Vec<Term>;
 pub struct GetTermsContextsRequest {
     pub retina_name: String,
     pub term: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub start_index: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub max_results: Option<u32>,
     pub get_fingerprint: bool,
 }
@@ -229,7 +527,10 @@ pub struct GetTermsContextsRequest {
 pub struct TermContext {
     pub fingerprint: Option<Fingerprint>,
     pub context: Option<String>,
+    #[serde(rename = "contextLabel")]
     pub context_label: Option<String>,
+    #[serde(rename = "contextId")]
+    pub context_id: Option<u32>,
 }
 
 pub type GetTermsContextsResponse = Vec<TermContext>;
@@ -238,9 +539,220 @@ pub type GetTermsContextsResponse = Vec<TermContext>;
 pub struct GetTermsSimilarTermsRequest {
     pub retina_name: String,
     pub term: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub context_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub pos_type: Option<PosType>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub start_index: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub max_results: Option<u32>,
     pub get_fingerprint: bool,
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use serde::de::DeserializeOwned;
+    use serde::Serialize;
+    use serde_json::Value;
+
+    use crate::{CompareResponse, CreateCategoryFilterResponse, Error, Fingerprint, RetinaShape, GetTermsContextsRequest, GetTermsContextsResponse, GetTermsResponse, GetTermsSimilarTermsRequest, LanguageResponse, PosType, Retina, TextSlice, TextSliceRequest};
+
+    fn strip_nulls(value: Value) -> Value {
+        match value {
+            Value::Object(map) =>
+                Value::Object(
+                    map.into_iter()
+                        .filter(|(_, v)| !v.is_null())
+                        .map(|(k, v)| (k, strip_nulls(v)))
+                        .collect()
+                ),
+            Value::Array(values) => Value::Array(values.into_iter().map(strip_nulls).collect()),
+            value => value,
+        }
+    }
+
+    /// Decodes a fixture payload and checks that encoding it again yields the same document. The
+    /// fixtures are handwritten in the API's format, see `fixtures/README.md`.
+    fn round_trip<T: DeserializeOwned + Serialize>(payload: &str) -> T {
+        let decoded: T = serde_json::from_str(payload).unwrap();
+
+        assert_eq!(
+            strip_nulls(serde_json::to_value(&decoded).unwrap()),
+            serde_json::from_str::<Value>(payload).unwrap(),
+        );
+
+        decoded
+    }
+
+    #[test]
+    fn retinas() {
+        let retinas: Vec<Retina> = round_trip(include_str!("../fixtures/retinas.json"));
+
+        assert_eq!(retinas[1].retina_name, "en_associative");
+        assert_eq!(retinas[1].number_of_columns, 128);
+        assert_eq!(retinas[1].number_of_terms_in_retina, 854523);
+        assert_eq!(RetinaShape::from(&retinas[1]), RetinaShape::default());
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn deprecated_expand_and_compare() {
+        let fingerprint = Fingerprint { positions: vec![1, 5] };
+        let other = Fingerprint { positions: vec![5, 9] };
+
+        assert_eq!(fingerprint.expand(6), fingerprint.expand_with_shape(RetinaShape::new(2, 3)).unwrap());
+        assert_eq!(fingerprint.expand_t::<f64>(16384), Vec::<f64>::try_from(fingerprint.clone()).unwrap());
+        assert_eq!(fingerprint.compare(&other), fingerprint.compare_with_shape(&other, RetinaShape::default()).unwrap());
+    }
+
+    #[test]
+    fn expand_to_shape() {
+        let shape = RetinaShape::new(2, 3);
+        let fingerprint = Fingerprint { positions: vec![1, 5] };
+
+        assert_eq!(fingerprint.expand_with_shape(shape).unwrap(), vec![0, 1, 0, 0, 0, 1]);
+        assert_eq!(shape.coordinates(5), (2, 1));
+        assert_eq!(shape.position(2, 1), 5);
+
+        assert!(matches!(
+            fingerprint.expand_with_shape(RetinaShape::new(1, 5)),
+            Err(Error::PositionOutOfRange { position: 5, len: 5 })
+        ));
+        assert!(matches!(
+            Vec::<f64>::try_from(Fingerprint { positions: vec![16384] }),
+            Err(Error::PositionOutOfRange { .. })
+        ));
+    }
+
+    #[test]
+    fn aggregate_by_votes() {
+        let shape = RetinaShape::new(2, 5);
+        let fingerprints = [
+            Fingerprint { positions: vec![0, 1, 2] },
+            Fingerprint { positions: vec![1, 2, 3, 3] },
+            Fingerprint { positions: vec![2, 3, 4] },
+        ];
+
+        let aggregate = |weights: Option<&[f64]>, sparsity: f64| {
+            Fingerprint::aggregate_with_shape(&fingerprints, weights, sparsity, shape)
+                .map(|fingerprint| fingerprint.positions)
+        };
+
+        // 2 has three votes, 1 and 3 have two, 0 and 4 one; ties go to the lower position
+        assert_eq!(aggregate(None, 0.3).unwrap(), vec![1, 2, 3]);
+        assert_eq!(aggregate(None, 0.4).unwrap(), vec![0, 1, 2, 3]);
+        assert_eq!(aggregate(None, 1.0).unwrap(), vec![0, 1, 2, 3, 4]);
+        assert_eq!(aggregate(Some(&[1.0, 0.0, 5.0]), 0.3).unwrap(), vec![2, 3, 4]);
+
+        assert!(matches!(aggregate(None, 1.5), Err(Error::Config(_))));
+        assert!(matches!(aggregate(Some(&[1.0]), 0.3), Err(Error::Config(_))));
+        assert!(matches!(aggregate(Some(&[1.0, -1.0, 1.0]), 0.3), Err(Error::Config(_))));
+
+        let dense = [Fingerprint { positions: (0..1000).collect() }];
+
+        assert_eq!(Fingerprint::aggregate(&dense, 0.02).unwrap().positions.len(), 328);
+        assert_eq!(Fingerprint::aggregate_weighted(&dense, &[2.0], 0.0).unwrap(), Fingerprint::default());
+    }
+
+    #[test]
+    fn terms() {
+        let terms: GetTermsResponse = round_trip(include_str!("../fixtures/terms.json"));
+
+        assert_eq!(terms[0].pos_types, Some(vec![PosType::Noun]));
+        assert_eq!(terms[0].fingerprint.as_ref().unwrap().positions.len(), 16);
+
+        let terms: GetTermsResponse = round_trip(include_str!("../fixtures/terms_similar_terms.json"));
+
+        assert_eq!(terms[2].pos_types, Some(vec![PosType::Adjective, PosType::Noun]));
+        assert_eq!(terms[2].score, Some(44.0));
+    }
+
+    #[test]
+    fn terms_contexts() {
+        let contexts: GetTermsContextsResponse = round_trip(include_str!("../fixtures/terms_contexts.json"));
+
+        assert_eq!(contexts[1].context_label.as_deref(), Some("fruit"));
+        assert_eq!(contexts[1].context_id, Some(1));
+    }
+
+    #[test]
+    fn text_slices() {
+        let slices: Vec<TextSlice> = round_trip(include_str!("../fixtures/text_slices.json"));
+
+        assert_eq!(slices[0].fingerprint.as_ref().unwrap().positions[0], 129);
+        assert_eq!(slices[1].fingerprint, None);
+    }
+
+    #[test]
+    fn compare() {
+        let compare: CompareResponse = round_trip(include_str!("../fixtures/compare.json"));
+
+        assert_eq!(compare.size_left, 506);
+        assert_eq!(compare.overlapping_all, 215);
+        assert_eq!(compare.overlapping_right_left, 0.4013);
+    }
+
+    #[test]
+    fn detect_language() {
+        let language: LanguageResponse = round_trip(include_str!("../fixtures/detect_language.json"));
+
+        assert_eq!(language.iso_tag.as_deref(), Some("de"));
+        assert!(language.wiki_url.is_some());
+    }
+
+    #[test]
+    fn category_filter() {
+        let filter: CreateCategoryFilterResponse = round_trip(include_str!("../fixtures/category_filter.json"));
+
+        assert_eq!(filter.category_name, "fruit");
+        assert_eq!(filter.positions.len(), 6);
+    }
+
+    #[test]
+    fn query_parameters() {
+        let query =
+            serde_json::to_value(
+                GetTermsSimilarTermsRequest {
+                    retina_name: "en_general".to_string(),
+                    term: "apple".to_string(),
+                    pos_type: Some(PosType::Noun),
+                    max_results: Some(10),
+                    ..Default::default()
+                }
+            ).unwrap();
+
+        assert_eq!(
+            query,
+            serde_json::json!({
+                "retina_name": "en_general",
+                "term": "apple",
+                "pos_type": "NOUN",
+                "max_results": 10,
+                "get_fingerprint": false
+            })
+        );
+
+        let query =
+            serde_json::to_value(
+                GetTermsContextsRequest {
+                    retina_name: "en_general".to_string(),
+                    term: "apple".to_string(),
+                    ..Default::default()
+                }
+            ).unwrap();
+
+        assert_eq!(query.as_object().unwrap().len(), 3);
+
+        let query = serde_json::to_value(TextSliceRequest::new()).unwrap();
+
+        assert_eq!(
+            query,
+            serde_json::json!({
+                "retina_name": "en_general",
+                "start_index": 0,
+                "max_results": 10,
+                "get_fingerprint": false
+            })
+        );
+    }
+}
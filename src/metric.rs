@@ -0,0 +1,327 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::similarity::{sorted_overlap, sorted_positions, FingerprintSimilarity};
+use crate::spatial::{BlurredCosine, EarthMovers, RadiusOverlap};
+use crate::{BitFingerprint, Error, Fingerprint, RetinaShape};
+
+/// Whether a higher score means more or less alike.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum MetricKind {
+    Similarity,
+    Distance,
+}
+
+impl MetricKind {
+    /// Orders two scores best first: descending for similarities, ascending for distances. NaN
+    /// sorts last either way.
+    pub fn rank(&self, a: f64, b: f64) -> Ordering {
+        match (a.is_nan(), b.is_nan()) {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Greater,
+            (false, true) => Ordering::Less,
+            (false, false) if *self == MetricKind::Distance => a.total_cmp(&b),
+            (false, false) => b.total_cmp(&a),
+        }
+    }
+}
+
+/// A way of scoring a pair of binary fingerprints.
+///
+/// Most metrics are computed from the counts in a [`FingerprintSimilarity`], so scoring two
+/// [`BitFingerprint`]s only takes a few popcounts. Spatial metrics, see [`crate::spatial`], look
+/// at where the positions sit on the retina instead; they can't be scored from counts and
+/// override the scoring of fingerprints and positions. Ranking,
+/// clustering and search take a `&dyn SimilarityMetric` and use [`SimilarityMetric::rank`] to
+/// order scores best first.
+pub trait SimilarityMetric: Send + Sync {
+    /// The name the metric is registered under.
+    fn name(&self) -> &str;
+
+    fn kind(&self) -> MetricKind;
+
+    /// The score from the counts alone, or `None` when they are not enough, as for spatial
+    /// metrics. Metrics answering `None` override [`SimilarityMetric::score`] and
+    /// [`SimilarityMetric::score_positions`].
+    fn score_counts(&self, counts: &FingerprintSimilarity) -> Option<f64>;
+
+    fn score(&self, a: &BitFingerprint, b: &BitFingerprint) -> f64 {
+        self.score_counts(&FingerprintSimilarity::from_bits(a, b))
+            .expect("metrics without a score from counts override score")
+    }
+
+    /// Scores two fingerprints of a retina of any shape.
+    fn score_fingerprints(&self, a: &Fingerprint, b: &Fingerprint, shape: RetinaShape) -> Result<f64, Error> {
+        Ok(self.score_positions(&sorted_positions(a, shape)?, &sorted_positions(b, shape)?, shape))
+    }
+
+    /// Scores two sorted, deduplicated lists of positions of `shape`.
+    fn score_positions(&self, a: &[u32], b: &[u32], shape: RetinaShape) -> f64 {
+        self.score_counts(&FingerprintSimilarity::from_counts(a.len() as u32, b.len() as u32, sorted_overlap(a, b), shape.len()))
+            .expect("metrics without a score from counts override score_positions")
+    }
+
+    /// Whether the score depends on where positions sit rather than on the counts alone. Spatial
+    /// metrics answer [`SimilarityMetric::score_counts`] with `None`.
+    fn is_spatial(&self) -> bool {
+        false
+    }
+
+    /// How many rows and columns apart two positions may sit and still count towards the score.
+    /// `Some` promises that two fingerprints without such a pair of positions score worse than
+    /// any two with one, which lets [`FingerprintIndex`](crate::index::FingerprintIndex) skip
+    /// them. `None`, the default, when they can score better, as with [`Hamming`] and
+    /// [`Euclidean`], or when positions at any distance count.
+    fn radius(&self) -> Option<u32> {
+        None
+    }
+
+    fn is_distance(&self) -> bool {
+        self.kind() == MetricKind::Distance
+    }
+
+    /// Orders two scores best first, see [`MetricKind::rank`].
+    fn rank(&self, a: f64, b: f64) -> Ordering {
+        self.kind().rank(a, b)
+    }
+}
+
+/// Defines a unit struct that scores with one of the [`FingerprintSimilarity`] methods.
+macro_rules! count_metric {
+    ($(#[$doc:meta])* $metric:ident, $name:literal, $kind:ident, |$counts:ident| $score:expr $(, radius $radius:expr)?) => {
+        $(#[$doc])*
+        #[derive(Debug, Clone, Copy, Default, PartialEq)]
+        pub struct $metric;
+
+        impl SimilarityMetric for $metric {
+            fn name(&self) -> &str {
+                $name
+            }
+
+            fn kind(&self) -> MetricKind {
+                MetricKind::$kind
+            }
+
+            fn score_counts(&self, $counts: &FingerprintSimilarity) -> Option<f64> {
+                Some($score)
+            }
+
+            $(
+                fn radius(&self) -> Option<u32> {
+                    Some($radius)
+                }
+            )?
+        }
+    };
+}
+
+count_metric!(
+    /// [`FingerprintSimilarity::cosine_similarity`], shifted into 0..1.
+    Cosine, "cosine", Similarity, |counts| counts.cosine_similarity(), radius 0
+);
+count_metric!(Euclidean, "euclidean", Distance, |counts| counts.euclidean_distance());
+count_metric!(NormalizedEuclidean, "normalized_euclidean", Distance, |counts| counts.normalized_euclidean_distance());
+count_metric!(NormalizedEuclideanSimilarity, "normalized_euclidean_similarity", Similarity, |counts| counts.normalized_euclidean_similarity());
+count_metric!(Jaccard, "jaccard", Similarity, |counts| counts.jaccard_index(), radius 0);
+count_metric!(JaccardDistance, "jaccard_distance", Distance, |counts| counts.jaccard_distance(), radius 0);
+count_metric!(Hamming, "hamming", Distance, |counts| counts.hamming_distance() as f64);
+count_metric!(
+    /// The number of shared positions.
+    Overlap, "overlap", Similarity, |counts| counts.overlapping_all() as f64, radius 0
+);
+count_metric!(WeightedScoring, "weighted_scoring", Similarity, |counts| counts.weighted_scoring());
+count_metric!(Pearson, "pearson", Similarity, |counts| counts.pearson_r_coeff());
+count_metric!(Dice, "dice", Similarity, |counts| counts.dice(), radius 0);
+count_metric!(
+    /// The Sørensen–Dice coefficient under its other name, the same score as [`Dice`].
+    Sorensen, "sorensen", Similarity, |counts| counts.dice(), radius 0
+);
+count_metric!(SorensenDistance, "sorensen_distance", Distance, |counts| counts.sorensen_distance(), radius 0);
+count_metric!(OverlapCoefficient, "overlap_coefficient", Similarity, |counts| counts.overlap_coefficient(), radius 0);
+
+/// [`FingerprintSimilarity::tversky`] with fixed weights for the left-only and right-only positions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tversky {
+    pub alpha: f64,
+    pub beta: f64,
+}
+
+impl Tversky {
+    pub fn new(alpha: f64, beta: f64) -> Self {
+        Self {
+            alpha,
+            beta,
+        }
+    }
+}
+
+impl SimilarityMetric for Tversky {
+    fn name(&self) -> &str {
+        "tversky"
+    }
+
+    fn kind(&self) -> MetricKind {
+        MetricKind::Similarity
+    }
+
+    fn score_counts(&self, counts: &FingerprintSimilarity) -> Option<f64> {
+        Some(counts.tversky(self.alpha, self.beta))
+    }
+
+    fn radius(&self) -> Option<u32> {
+        Some(0)
+    }
+}
+
+/// Looks metrics up by name, e.g. to pick one from configuration.
+#[derive(Clone, Default)]
+pub struct MetricRegistry {
+    metrics: BTreeMap<String, Arc<dyn SimilarityMetric>>,
+}
+
+impl MetricRegistry {
+    /// An empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A registry holding every built-in metric. Tversky is registered as "tversky" with
+    /// α = β = 0.5, "radius_overlap" with a radius of 1 and "blurred_cosine" with σ = 1; register
+    /// another instance to change the parameters.
+    pub fn with_builtins() -> Self {
+        let mut registry = Self::new();
+
+        registry.register(Cosine);
+        registry.register(Euclidean);
+        registry.register(NormalizedEuclidean);
+        registry.register(NormalizedEuclideanSimilarity);
+        registry.register(Jaccard);
+        registry.register(JaccardDistance);
+        registry.register(Hamming);
+        registry.register(Overlap);
+        registry.register(WeightedScoring);
+        registry.register(Pearson);
+        registry.register(Dice);
+        registry.register(Sorensen);
+        registry.register(SorensenDistance);
+        registry.register(OverlapCoefficient);
+        registry.register(Tversky::new(0.5, 0.5));
+        registry.register(RadiusOverlap::new(1));
+        registry.register(BlurredCosine::new(1.0));
+        registry.register(EarthMovers);
+
+        registry
+    }
+
+    /// Adds `metric` under its name, returning the metric it replaces.
+    pub fn register(&mut self, metric: impl SimilarityMetric + 'static) -> Option<Arc<dyn SimilarityMetric>> {
+        self.metrics.insert(metric.name().to_string(), Arc::new(metric))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn SimilarityMetric> {
+        self.metrics
+            .get(name)
+            .map(|metric| metric.as_ref())
+    }
+
+    /// The registered names in alphabetical order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.metrics.keys().map(|name| name.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use crate::similarity::FingerprintSimilarity;
+    use crate::{BitFingerprint, Fingerprint, RetinaShape};
+
+    use super::{Dice, Jaccard, JaccardDistance, MetricKind, MetricRegistry, SimilarityMetric, Tversky};
+
+    fn bits(positions: &[u32]) -> BitFingerprint {
+        BitFingerprint::try_from(&Fingerprint { positions: positions.to_vec() }).unwrap()
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-12, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn builtins_match_inherent_methods() {
+        let registry = MetricRegistry::with_builtins();
+        let (left, right) = (bits(&[1, 2, 3, 4]), bits(&[3, 4, 5, 6, 7]));
+        let counts = FingerprintSimilarity::from_bits(&left, &right);
+
+        let score = |name: &str| registry.get(name).unwrap().score(&left, &right);
+
+        assert_close(score("cosine"), counts.cosine_similarity());
+        assert_close(score("euclidean"), counts.euclidean_distance());
+        assert_close(score("normalized_euclidean"), counts.normalized_euclidean_distance());
+        assert_close(score("normalized_euclidean_similarity"), counts.normalized_euclidean_similarity());
+        assert_close(score("jaccard"), 2.0 / 7.0);
+        assert_close(score("jaccard_distance"), 5.0 / 7.0);
+        assert_close(score("hamming"), 5.0);
+        assert_close(score("overlap"), 2.0);
+        assert_close(score("weighted_scoring"), counts.weighted_scoring());
+        assert_close(score("pearson"), counts.pearson_r_coeff());
+        assert_close(score("dice"), 4.0 / 9.0);
+        assert_close(score("sorensen"), 4.0 / 9.0);
+        assert_close(score("sorensen_distance"), 5.0 / 9.0);
+        assert_close(score("overlap_coefficient"), 0.5);
+        assert_close(score("tversky"), 4.0 / 9.0);
+
+        assert_eq!(registry.get("sorensen").unwrap().kind(), MetricKind::Similarity);
+        assert_eq!(registry.get("sorensen_distance").unwrap().kind(), MetricKind::Distance);
+
+        assert_eq!(registry.names().count(), 18);
+        assert!(registry.get("manhattan").is_none());
+    }
+
+    #[test]
+    fn tversky_generalizes_jaccard_and_dice() {
+        let (left, right) = (bits(&[1, 2, 3]), bits(&[2, 3, 4, 5, 6]));
+
+        assert_close(Tversky::new(1.0, 1.0).score(&left, &right), Jaccard.score(&left, &right));
+        assert_close(Tversky::new(0.5, 0.5).score(&left, &right), Dice.score(&left, &right));
+
+        // only the left-only position counts: 2 / (2 + 1)
+        assert_close(Tversky::new(1.0, 0.0).score(&left, &right), 2.0 / 3.0);
+    }
+
+    #[test]
+    fn ranks_best_first() {
+        let mut similarities = [0.2, f64::NAN, 0.9, 0.5];
+        similarities.sort_by(|a, b| Jaccard.rank(*a, *b));
+
+        assert_eq!(&similarities[..3], &[0.9, 0.5, 0.2]);
+        assert!(similarities[3].is_nan());
+
+        let mut distances = [0.2, f64::NAN, 0.9, 0.5];
+        distances.sort_by(|a, b| JaccardDistance.rank(*a, *b));
+
+        assert_eq!(&distances[..3], &[0.2, 0.5, 0.9]);
+        assert_eq!(JaccardDistance.rank(0.1, 0.1), Ordering::Equal);
+    }
+
+    #[test]
+    fn registers_custom_metrics() {
+        let mut registry = MetricRegistry::with_builtins();
+
+        let replaced = registry.register(Tversky::new(0.9, 0.1));
+
+        assert_close(replaced.unwrap().score(&bits(&[1]), &bits(&[1, 2])), 2.0 / 3.0);
+
+        let metric: &dyn SimilarityMetric = registry.get("tversky").unwrap();
+
+        assert_eq!(metric.kind(), MetricKind::Similarity);
+        assert_close(metric.score(&bits(&[1]), &bits(&[1, 2])), 1.0 / 1.1);
+        assert_close(
+            metric.score_fingerprints(&Fingerprint { positions: vec![1] }, &Fingerprint { positions: vec![1, 2] }, RetinaShape::new(1, 3)).unwrap(),
+            1.0 / 1.1,
+        );
+    }
+}
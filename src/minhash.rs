@@ -0,0 +1,484 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+use serde::{Deserialize, Serialize};
+
+use crate::similarity::{ratio, sorted_overlap, sorted_positions};
+use crate::{Error, Fingerprint, RetinaShape};
+
+/// Computes MinHash signatures of fingerprints and splits them into LSH bands.
+///
+/// A signature has `bands * rows` values. Two fingerprints land in the same bucket of a band when
+/// all `rows` values of that band agree, which happens with probability `J^rows` for a Jaccard
+/// index of `J`; more bands catch more pairs. The hash functions only depend on `seed`, so
+/// signatures stay comparable across processes and can be persisted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "MinHasherParams", into = "MinHasherParams")]
+pub struct MinHasher {
+    bands: usize,
+    rows: usize,
+    seed: u64,
+}
+
+/// The serialized form of a [`MinHasher`], checked by [`MinHasher::new`] when decoded.
+#[derive(Serialize, Deserialize)]
+struct MinHasherParams {
+    bands: usize,
+    rows: usize,
+    seed: u64,
+}
+
+impl TryFrom<MinHasherParams> for MinHasher {
+    type Error = Error;
+
+    fn try_from(params: MinHasherParams) -> Result<Self, Error> {
+        MinHasher::new(params.bands, params.rows, params.seed)
+    }
+}
+
+impl From<MinHasher> for MinHasherParams {
+    fn from(hasher: MinHasher) -> Self {
+        Self {
+            bands: hasher.bands,
+            rows: hasher.rows,
+            seed: hasher.seed,
+        }
+    }
+}
+
+/// The MinHash signature of a fingerprint.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Signature {
+    pub values: Vec<u64>,
+}
+
+impl MinHasher {
+    pub fn new(bands: usize, rows: usize, seed: u64) -> Result<Self, Error> {
+        if bands == 0 || rows == 0 {
+            return Err(Error::Config(format!("MinHash needs at least one band and one row, got {} bands of {} rows", bands, rows)));
+        }
+
+        Ok(
+            Self {
+                bands,
+                rows,
+                seed,
+            }
+        )
+    }
+
+    pub fn bands(&self) -> usize {
+        self.bands
+    }
+
+    /// The number of signature values per band.
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// The number of hash functions, `bands * rows`.
+    pub fn len(&self) -> usize {
+        self.bands * self.rows
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The Jaccard index at which a pair becomes a candidate with probability of about 1/2,
+    /// `(1 / bands)^(1 / rows)`.
+    pub fn threshold(&self) -> f64 {
+        (1.0 / self.bands as f64).powf(1.0 / self.rows as f64)
+    }
+
+    /// The probability that two fingerprints with Jaccard index `jaccard` share a bucket.
+    pub fn candidate_probability(&self, jaccard: f64) -> f64 {
+        1.0 - (1.0 - jaccard.powi(self.rows as i32)).powi(self.bands as i32)
+    }
+
+    /// Signs a fingerprint. An empty fingerprint gets `u64::MAX` everywhere.
+    pub fn sign(&self, fingerprint: &Fingerprint) -> Signature {
+        let values =
+            (0..self.len())
+                .map(|i| {
+                    let seed = mix(self.seed.wrapping_add((i as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15)));
+
+                    fingerprint.positions
+                        .iter()
+                        .map(|position| mix(*position as u64 ^ seed))
+                        .min()
+                        .unwrap_or(u64::MAX)
+                })
+                .collect();
+
+        Signature {
+            values,
+        }
+    }
+
+    /// One bucket key per band.
+    pub fn band_keys<'a>(&'a self, signature: &'a Signature) -> impl Iterator<Item = u64> + 'a {
+        signature.values
+            .chunks(self.rows)
+            .take(self.bands)
+            .enumerate()
+            .map(move |(band, rows)| {
+                rows.iter()
+                    .fold(mix(self.seed ^ band as u64), |key, value| mix(key ^ value))
+            })
+    }
+}
+
+impl Signature {
+    /// The fraction of agreeing values, an unbiased estimate of the Jaccard index. Values that are
+    /// `u64::MAX` on both sides only say that both fingerprints are empty and don't count, so two
+    /// empty fingerprints estimate 0 like their exact index.
+    pub fn estimate_jaccard(&self, other: &Signature) -> f64 {
+        if self.values.is_empty() {
+            return 0.0;
+        }
+
+        let agreeing =
+            self.values
+                .iter()
+                .zip(other.values.iter())
+                .filter(|(l, r)| l == r && **l != u64::MAX)
+                .count();
+
+        agreeing as f64 / self.values.len() as f64
+    }
+}
+
+/// The splitmix64 finalizer.
+pub(crate) fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// Buckets fingerprints by their LSH band keys for approximate near-duplicate search.
+///
+/// Only the signature of each fingerprint is kept, so the index stays small when the corpus itself
+/// lives elsewhere, e.g. on disk. Candidates from shared buckets are verified with the exact
+/// Jaccard index of the fingerprints a caller-supplied `fetch` returns for their ids, so results
+/// never contain false positives; pairs that share no bucket are missed.
+#[derive(Debug, Clone)]
+pub struct LshIndex<K> {
+    hasher: MinHasher,
+    shape: RetinaShape,
+    buckets: Vec<HashMap<u64, Vec<K>>>,
+    signatures: HashMap<K, Signature>,
+}
+
+impl<K: Clone + Eq + Hash + Ord> LshIndex<K> {
+    /// An empty index for fingerprints of the default 128x128 retina.
+    pub fn new(hasher: MinHasher) -> Self {
+        Self::with_shape(hasher, RetinaShape::default())
+    }
+
+    pub fn with_shape(hasher: MinHasher, shape: RetinaShape) -> Self {
+        Self {
+            hasher,
+            shape,
+            buckets: vec![HashMap::new(); hasher.bands()],
+            signatures: HashMap::new(),
+        }
+    }
+
+    pub fn hasher(&self) -> &MinHasher {
+        &self.hasher
+    }
+
+    pub fn shape(&self) -> RetinaShape {
+        self.shape
+    }
+
+    pub fn len(&self) -> usize {
+        self.signatures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.signatures.is_empty()
+    }
+
+    /// Adds a fingerprint, replacing the one stored under `id` if there is one.
+    pub fn insert(&mut self, id: K, fingerprint: &Fingerprint) -> Result<(), Error> {
+        sorted_positions(fingerprint, self.shape)?;
+
+        self.insert_signature(id, self.hasher.sign(fingerprint))
+    }
+
+    /// Adds a signature made by this index's hasher, e.g. one persisted earlier. Fails with
+    /// [`Error::SignatureLength`] unless it has `bands * rows` values.
+    pub fn insert_signature(&mut self, id: K, signature: Signature) -> Result<(), Error> {
+        if signature.values.len() != self.hasher.len() {
+            return Err(Error::SignatureLength { expected: self.hasher.len(), actual: signature.values.len() });
+        }
+
+        self.delete(&id);
+
+        for (band, key) in self.hasher.band_keys(&signature).enumerate() {
+            self.buckets[band]
+                .entry(key)
+                .or_default()
+                .push(id.clone());
+        }
+
+        self.signatures.insert(id, signature);
+
+        Ok(())
+    }
+
+    /// Removes the fingerprint stored under `id`, returning whether there was one.
+    pub fn delete(&mut self, id: &K) -> bool {
+        let signature =
+            match self.signatures.remove(id) {
+                Some(signature) => signature,
+                None => return false,
+            };
+
+        for (band, key) in self.hasher.band_keys(&signature).enumerate() {
+            if let Some(bucket) = self.buckets[band].get_mut(&key) {
+                bucket.retain(|other| other != id);
+
+                if bucket.is_empty() {
+                    self.buckets[band].remove(&key);
+                }
+            }
+        }
+
+        true
+    }
+
+    /// The signature stored under `id`, e.g. to persist it.
+    pub fn signature(&self, id: &K) -> Option<&Signature> {
+        self.signatures.get(id)
+    }
+
+    /// Every id sharing at least one bucket with `fingerprint`, unverified.
+    pub fn candidates(&self, fingerprint: &Fingerprint) -> HashSet<K> {
+        let signature = self.hasher.sign(fingerprint);
+
+        self.hasher
+            .band_keys(&signature)
+            .enumerate()
+            .filter_map(|(band, key)| self.buckets[band].get(&key))
+            .flatten()
+            .cloned()
+            .collect()
+    }
+
+    /// The candidates whose exact Jaccard index with `fingerprint` is at least `min_jaccard`, best
+    /// first. `fetch` returns the fingerprint stored under an id; ids it has none for are skipped.
+    pub fn query(
+        &self,
+        fingerprint: &Fingerprint,
+        min_jaccard: f64,
+        mut fetch: impl FnMut(&K) -> Option<Fingerprint>,
+    ) -> Result<Vec<(K, f64)>, Error> {
+        let positions = sorted_positions(fingerprint, self.shape)?;
+
+        let mut matches = Vec::new();
+
+        for id in self.candidates(fingerprint) {
+            let candidate =
+                match fetch(&id) {
+                    Some(candidate) => sorted_positions(&candidate, self.shape)?,
+                    None => continue,
+                };
+
+            let jaccard = jaccard(&positions, &candidate);
+
+            if jaccard >= min_jaccard {
+                matches.push((id, jaccard));
+            }
+        }
+
+        matches.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+        Ok(matches)
+    }
+
+    /// Every pair of stored fingerprints sharing a bucket whose exact Jaccard index is at least
+    /// `min_jaccard`, best first. Each pair is reported once, with the smaller id first. `fetch`
+    /// is called at most once per id, as in [`LshIndex::query`].
+    pub fn near_duplicates(
+        &self,
+        min_jaccard: f64,
+        mut fetch: impl FnMut(&K) -> Option<Fingerprint>,
+    ) -> Result<Vec<(K, K, f64)>, Error> {
+        let pairs =
+            self.buckets
+                .iter()
+                .flat_map(|buckets| buckets.values())
+                .flat_map(|bucket| {
+                    bucket.iter().enumerate().flat_map(move |(i, left)| {
+                        bucket[i + 1..].iter().map(move |right| {
+                            if left < right { (left, right) } else { (right, left) }
+                        })
+                    })
+                })
+                .collect::<HashSet<_>>();
+
+        let mut fetched = HashMap::<&K, Option<Vec<u32>>>::new();
+        let mut duplicates = Vec::new();
+
+        for (left, right) in pairs {
+            for id in [left, right] {
+                if !fetched.contains_key(id) {
+                    let positions =
+                        fetch(id)
+                            .map(|fingerprint| sorted_positions(&fingerprint, self.shape))
+                            .transpose()?;
+
+                    fetched.insert(id, positions);
+                }
+            }
+
+            if let (Some(l), Some(r)) = (&fetched[left], &fetched[right]) {
+                let jaccard = jaccard(l, r);
+
+                if jaccard >= min_jaccard {
+                    duplicates.push((left.clone(), right.clone(), jaccard));
+                }
+            }
+        }
+
+        duplicates.sort_by(|a, b| b.2.total_cmp(&a.2).then_with(|| (&a.0, &a.1).cmp(&(&b.0, &b.1))));
+
+        Ok(duplicates)
+    }
+}
+
+/// |A ∩ B| / |A ∪ B| of two sorted, deduplicated position lists, or 0 when both are empty.
+fn jaccard(left: &[u32], right: &[u32]) -> f64 {
+    let overlap = sorted_overlap(left, right) as f64;
+
+    ratio(overlap, left.len() as f64 + right.len() as f64 - overlap)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{Error, Fingerprint, RetinaShape};
+
+    use super::{LshIndex, MinHasher};
+
+    fn fingerprint(positions: impl IntoIterator<Item = u32>) -> Fingerprint {
+        Fingerprint { positions: positions.into_iter().collect() }
+    }
+
+    #[test]
+    fn signatures_are_deterministic() {
+        let hasher = MinHasher::new(16, 4, 42).unwrap();
+        let print = fingerprint([3, 90, 1200, 16000]);
+
+        assert_eq!(hasher.sign(&print), MinHasher::new(16, 4, 42).unwrap().sign(&print));
+        assert_ne!(hasher.sign(&print), MinHasher::new(16, 4, 43).unwrap().sign(&print));
+        assert_eq!(hasher.sign(&print).values.len(), 64);
+
+        // positions are a set: order and duplicates do not matter
+        assert_eq!(hasher.sign(&print), hasher.sign(&fingerprint([16000, 3, 1200, 90, 3])));
+
+        let signature = hasher.sign(&print);
+        let persisted: super::Signature = serde_json::from_str(&serde_json::to_string(&signature).unwrap()).unwrap();
+
+        assert_eq!(persisted, signature);
+        assert!(matches!(MinHasher::new(0, 4, 1), Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn estimates_jaccard() {
+        let hasher = MinHasher::new(64, 8, 7).unwrap();
+
+        // 300 shared positions out of 500
+        let left = fingerprint(0..400);
+        let right = fingerprint(100..500);
+
+        let estimate = hasher.sign(&left).estimate_jaccard(&hasher.sign(&right));
+
+        assert!((estimate - 0.6).abs() < 0.08, "estimate {}", estimate);
+        assert_eq!(hasher.sign(&left).estimate_jaccard(&hasher.sign(&left)), 1.0);
+
+        // two empty fingerprints share nothing, as with the exact index
+        let empty = hasher.sign(&fingerprint([]));
+
+        assert_eq!(empty.estimate_jaccard(&empty), super::jaccard(&[], &[]));
+        assert_eq!(empty.estimate_jaccard(&hasher.sign(&left)), 0.0);
+    }
+
+    #[test]
+    fn banding_parameters() {
+        let hasher = MinHasher::new(20, 5, 0).unwrap();
+
+        assert!((hasher.threshold() - 0.549).abs() < 1e-3);
+        assert!(hasher.candidate_probability(0.9) > 0.999);
+        assert!(hasher.candidate_probability(0.2) < 0.01);
+    }
+
+    #[test]
+    fn finds_verified_near_duplicates() {
+        let corpus =
+            HashMap::from([
+                (1, fingerprint(0..300)),
+                (2, fingerprint(5..300)),
+                (3, fingerprint(5000..5300)),
+                (4, fingerprint((0..300).step_by(2))),
+            ]);
+
+        let fetch = |id: &i32| corpus.get(id).cloned();
+
+        let mut index = LshIndex::new(MinHasher::new(20, 5, 1).unwrap());
+
+        for (id, fingerprint) in corpus.iter() {
+            index.insert(*id, fingerprint).unwrap();
+        }
+
+        let matches = index.query(&fingerprint(0..300), 0.9, fetch).unwrap();
+
+        assert_eq!(matches.iter().map(|(id, _)| *id).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(matches[1].1, 295.0 / 300.0);
+
+        let duplicates = index.near_duplicates(0.9, fetch).unwrap();
+
+        assert_eq!(duplicates, vec![(1, 2, 295.0 / 300.0)]);
+
+        // ids the caller can no longer fetch are skipped
+        assert_eq!(index.query(&fingerprint(0..300), 0.9, |id| fetch(id).filter(|_| *id != 1)).unwrap().len(), 1);
+
+        assert!(index.delete(&2));
+        assert!(!index.delete(&2));
+        assert!(index.near_duplicates(0.9, fetch).unwrap().is_empty());
+        assert_eq!(index.len(), 3);
+        assert!(index.signature(&1).is_some());
+    }
+
+    #[test]
+    fn validates_parameters_and_positions() {
+        let hasher = MinHasher::new(4, 2, 9).unwrap();
+
+        assert_eq!((hasher.bands(), hasher.rows(), hasher.seed()), (4, 2, 9));
+        assert_eq!(serde_json::from_str::<MinHasher>(&serde_json::to_string(&hasher).unwrap()).unwrap(), hasher);
+        assert!(serde_json::from_str::<MinHasher>(r#"{"bands":4,"rows":0,"seed":9}"#).is_err());
+
+        let mut index = LshIndex::with_shape(hasher, RetinaShape::new(2, 2));
+
+        assert!(matches!(index.insert(1, &fingerprint([4])), Err(Error::PositionOutOfRange { position: 4, len: 4 })));
+        assert!(index.is_empty());
+
+        index.insert_signature(1, hasher.sign(&fingerprint([0, 1]))).unwrap();
+
+        let short = MinHasher::new(2, 2, 9).unwrap().sign(&fingerprint([0, 1]));
+
+        assert!(matches!(index.insert_signature(2, short), Err(Error::SignatureLength { expected: 8, actual: 4 })));
+        assert_eq!(index.len(), 1);
+
+        assert_eq!(index.query(&fingerprint([0, 1]), 1.0, |_| Some(fingerprint([0, 1]))).unwrap(), vec![(1, 1.0)]);
+        assert!(index.query(&fingerprint([0, 1]), 1.0, |_| Some(fingerprint([0, 1, 7]))).is_err());
+    }
+}
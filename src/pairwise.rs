@@ -0,0 +1,360 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+use crate::metric::MetricKind;
+use crate::similarity::sorted_positions;
+use crate::{BitFingerprint, Error, Fingerprint, RetinaShape, SimilarityMetric};
+
+/// The scores of every pair out of `len` fingerprints.
+///
+/// The matrix is symmetric, so only the upper triangle (including the diagonal) is stored:
+/// `len * (len + 1) / 2` scores.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimilarityMatrix {
+    len: usize,
+    kind: MetricKind,
+    scores: Vec<f64>,
+}
+
+/// An entry of a ranking: the position of a fingerprint in its corpus and its score.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Neighbour {
+    pub index: usize,
+    pub score: f64,
+}
+
+impl SimilarityMatrix {
+    /// The number of fingerprints.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Whether the scores are similarities or distances.
+    pub fn kind(&self) -> MetricKind {
+        self.kind
+    }
+
+    fn offset(&self, i: usize, j: usize) -> usize {
+        let (i, j) = if i <= j { (i, j) } else { (j, i) };
+
+        i * self.len - i * i.saturating_sub(1) / 2 + (j - i)
+    }
+
+    /// The score of fingerprints `i` and `j`, in either order.
+    ///
+    /// Panics if either index is out of bounds.
+    pub fn get(&self, i: usize, j: usize) -> f64 {
+        assert!(i < self.len && j < self.len, "index ({}, {}) out of bounds for {} fingerprints", i, j, self.len);
+
+        self.scores[self.offset(i, j)]
+    }
+
+    /// The scores of fingerprint `i` against every fingerprint, itself included.
+    pub fn row(&self, i: usize) -> Vec<f64> {
+        (0..self.len)
+            .map(|j| self.get(i, j))
+            .collect()
+    }
+
+    /// Every pair `(i, j, score)` with `i < j`.
+    pub fn pairs(&self) -> impl Iterator<Item = (usize, usize, f64)> + '_ {
+        (0..self.len)
+            .flat_map(move |i| (i + 1..self.len).map(move |j| (i, j, self.get(i, j))))
+    }
+
+    /// The `k` best matches of fingerprint `i`, leaving out `i` itself.
+    pub fn top_k(&self, i: usize, k: usize) -> Vec<Neighbour> {
+        best_k(
+            (0..self.len)
+                .filter(|j| *j != i)
+                .map(|j| Neighbour { index: j, score: self.get(i, j) }),
+            k,
+            self.kind,
+        )
+    }
+}
+
+/// Scores every pair of fingerprints of the default 128x128 retina.
+pub fn pairwise(fingerprints: &[Fingerprint], metric: &dyn SimilarityMetric) -> Result<SimilarityMatrix, Error> {
+    let bits =
+        fingerprints
+            .iter()
+            .map(BitFingerprint::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(pairwise_bits(&bits, metric))
+}
+
+pub fn pairwise_bits(fingerprints: &[BitFingerprint], metric: &dyn SimilarityMetric) -> SimilarityMatrix {
+    matrix(fingerprints.len(), metric, |i, j| metric.score(&fingerprints[i], &fingerprints[j]))
+}
+
+/// Scores every pair of fingerprints of a retina of any shape.
+pub fn pairwise_with_shape(
+    fingerprints: &[Fingerprint],
+    shape: RetinaShape,
+    metric: &dyn SimilarityMetric,
+) -> Result<SimilarityMatrix, Error> {
+    let positions =
+        fingerprints
+            .iter()
+            .map(|fingerprint| sorted_positions(fingerprint, shape))
+            .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(matrix(positions.len(), metric, |i, j| metric.score_positions(&positions[i], &positions[j], shape)))
+}
+
+fn matrix(len: usize, metric: &dyn SimilarityMetric, score: impl Fn(usize, usize) -> f64 + Sync) -> SimilarityMatrix {
+    #[cfg(feature = "parallel")]
+    let scores =
+        (0..len)
+            .into_par_iter()
+            .flat_map_iter(|i| (i..len).map(move |j| (i, j)))
+            .map(|(i, j)| score(i, j))
+            .collect();
+
+    #[cfg(not(feature = "parallel"))]
+    let scores =
+        (0..len)
+            .flat_map(|i| (i..len).map(move |j| (i, j)))
+            .map(|(i, j)| score(i, j))
+            .collect();
+
+    SimilarityMatrix {
+        len,
+        kind: metric.kind(),
+        scores,
+    }
+}
+
+/// The `k` fingerprints of `corpus` that match `query` best, best first. Both must fit the default
+/// 128x128 retina.
+pub fn top_k(
+    query: &Fingerprint,
+    corpus: &[Fingerprint],
+    k: usize,
+    metric: &dyn SimilarityMetric,
+) -> Result<Vec<Neighbour>, Error> {
+    let query = BitFingerprint::try_from(query)?;
+
+    let corpus =
+        corpus
+            .iter()
+            .map(BitFingerprint::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(top_k_bits(&query, &corpus, k, metric))
+}
+
+/// Keeps at most `k` candidates in memory at any time, so the corpus can be arbitrarily large.
+pub fn top_k_bits(
+    query: &BitFingerprint,
+    corpus: &[BitFingerprint],
+    k: usize,
+    metric: &dyn SimilarityMetric,
+) -> Vec<Neighbour> {
+    let kind = metric.kind();
+
+    let neighbour = |(index, candidate): (usize, &BitFingerprint)| Neighbour { index, score: metric.score(query, candidate) };
+
+    #[cfg(feature = "parallel")]
+    let heap =
+        corpus
+            .par_iter()
+            .enumerate()
+            .map(neighbour)
+            .fold(BinaryHeap::new, |mut heap, neighbour| {
+                push_bounded(&mut heap, k, Candidate::new(neighbour, kind));
+                heap
+            })
+            .reduce(BinaryHeap::new, |mut heap, other| {
+                other.into_iter().for_each(|candidate| push_bounded(&mut heap, k, candidate));
+                heap
+            });
+
+    #[cfg(not(feature = "parallel"))]
+    let heap =
+        corpus
+            .iter()
+            .enumerate()
+            .map(neighbour)
+            .fold(BinaryHeap::new(), |mut heap, neighbour| {
+                push_bounded(&mut heap, k, Candidate::new(neighbour, kind));
+                heap
+            });
+
+    into_ranking(heap)
+}
+
+/// The `k` best of `neighbours`, best first.
+pub(crate) fn best_k(neighbours: impl Iterator<Item = Neighbour>, k: usize, kind: MetricKind) -> Vec<Neighbour> {
+    let mut heap = BinaryHeap::with_capacity(k.saturating_add(1).min(1024));
+
+    for neighbour in neighbours {
+        push_bounded(&mut heap, k, Candidate::new(neighbour, kind));
+    }
+
+    into_ranking(heap)
+}
+
+fn push_bounded(heap: &mut BinaryHeap<Candidate>, k: usize, candidate: Candidate) {
+    if k == 0 {
+        return;
+    }
+
+    if heap.len() < k {
+        heap.push(candidate);
+    } else if let Some(mut worst) = heap.peek_mut() {
+        if candidate < *worst {
+            *worst = candidate;
+        }
+    }
+}
+
+fn into_ranking(heap: BinaryHeap<Candidate>) -> Vec<Neighbour> {
+    heap.into_sorted_vec()
+        .into_iter()
+        .map(|candidate| candidate.neighbour)
+        .collect()
+}
+
+/// A heap entry ordered worst last, so the top of a max-heap is the candidate to evict. Ties go to
+/// the lower index.
+struct Candidate {
+    neighbour: Neighbour,
+    kind: MetricKind,
+}
+
+impl Candidate {
+    fn new(neighbour: Neighbour, kind: MetricKind) -> Self {
+        Self {
+            neighbour,
+            kind,
+        }
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.kind
+            .rank(self.neighbour.score, other.neighbour.score)
+            .then(self.neighbour.index.cmp(&other.neighbour.index))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+#[cfg(test)]
+mod tests {
+    use crate::metric::{Hamming, Jaccard};
+    use crate::similarity::FingerprintSimilarity;
+    use crate::spatial::RadiusOverlap;
+    use crate::{Error, Fingerprint, RetinaShape, SimilarityMetric};
+
+    use super::{pairwise, pairwise_with_shape, top_k, Neighbour};
+
+    fn fingerprints() -> Vec<Fingerprint> {
+        [
+            vec![1, 2, 3, 4],
+            vec![3, 4, 5, 6],
+            vec![1, 2, 3, 4, 5],
+            vec![100, 200],
+            vec![],
+        ]
+            .into_iter()
+            .map(|positions| Fingerprint { positions })
+            .collect()
+    }
+
+    #[test]
+    fn matrix_is_symmetric_and_matches_metric() {
+        let fingerprints = fingerprints();
+
+        let matrix = pairwise(&fingerprints, &Jaccard).unwrap();
+
+        assert_eq!(matrix.len(), 5);
+
+        for i in 0..5 {
+            for j in 0..5 {
+                let expected = Jaccard.score_counts(&FingerprintSimilarity::new(&fingerprints[i], &fingerprints[j]).unwrap()).unwrap();
+
+                assert_eq!(matrix.get(i, j), expected);
+                assert_eq!(matrix.get(j, i), expected);
+            }
+        }
+
+        assert_eq!(matrix.row(0), vec![1.0, 2.0 / 6.0, 0.8, 0.0, 0.0]);
+        assert_eq!(matrix.pairs().count(), 10);
+        assert_eq!(matrix.pairs().next(), Some((0, 1, 2.0 / 6.0)));
+    }
+
+    #[test]
+    fn matrix_with_shape() {
+        let fingerprints = fingerprints();
+
+        let small = pairwise_with_shape(&fingerprints[..3], RetinaShape::new(1, 8), &Hamming).unwrap();
+
+        assert_eq!(small.get(0, 1), 4.0);
+        assert_eq!(small.get(2, 2), 0.0);
+
+        // spatial metrics see the positions, not only the counts
+        let spatial = pairwise_with_shape(&fingerprints[..3], RetinaShape::new(1, 8), &RadiusOverlap::new(1)).unwrap();
+
+        assert_eq!(spatial.get(0, 1), 0.75);
+
+        assert!(matches!(
+            pairwise_with_shape(&fingerprints, RetinaShape::new(1, 8), &Hamming),
+            Err(Error::PositionOutOfRange { position: 100, len: 8 })
+        ));
+        assert!(pairwise(&[], &Jaccard).unwrap().is_empty());
+    }
+
+    #[test]
+    fn top_k_keeps_the_best() {
+        let corpus = fingerprints();
+        let query = Fingerprint { positions: vec![1, 2, 3] };
+
+        let best = top_k(&query, &corpus, 2, &Jaccard).unwrap();
+
+        assert_eq!(
+            best,
+            vec![Neighbour { index: 0, score: 0.75 }, Neighbour { index: 2, score: 0.6 }]
+        );
+
+        // distances rank ascending; ties go to the lower index
+        let closest = top_k(&query, &corpus, 3, &Hamming).unwrap();
+
+        assert_eq!(closest.iter().map(|n| n.index).collect::<Vec<_>>(), vec![0, 2, 4]);
+        assert_eq!(top_k(&query, &corpus, 10, &Jaccard).unwrap().len(), 5);
+        assert!(top_k(&query, &corpus, 0, &Jaccard).unwrap().is_empty());
+    }
+
+    #[test]
+    fn matrix_top_k_skips_itself() {
+        let matrix = pairwise(&fingerprints(), &Jaccard).unwrap();
+
+        assert_eq!(
+            matrix.top_k(0, 2).iter().map(|n| n.index).collect::<Vec<_>>(),
+            vec![2, 1]
+        );
+    }
+}
@@ -0,0 +1,166 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+
+/// Throttles outgoing requests with a token bucket and caps the number of requests in flight.
+///
+/// Clones share the same bucket and the same semaphore, so every clone of a client draws from
+/// one budget.
+#[derive(Debug, Clone, Default)]
+pub struct RateLimiter {
+    bucket: Option<Arc<TokenBucket>>,
+    in_flight: Option<Arc<Semaphore>>,
+}
+
+/// Holds a slot of the in-flight limit until dropped.
+#[derive(Debug)]
+pub struct Permit {
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl RateLimiter {
+    /// A limiter that never waits.
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
+
+    /// Allows `requests_per_second` on average, and up to `burst` requests at once after idling.
+    pub fn with_rate(mut self, requests_per_second: f64, burst: u32) -> Self {
+        self.bucket = Some(Arc::new(TokenBucket::new(requests_per_second, burst)));
+        self
+    }
+
+    pub fn with_max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.in_flight = Some(Arc::new(Semaphore::new(max_in_flight.max(1))));
+        self
+    }
+
+    /// Waits for a token and a free slot.
+    pub async fn acquire(&self) -> Permit {
+        if let Some(bucket) = &self.bucket {
+            bucket.acquire().await;
+        }
+
+        let permit =
+            match &self.in_flight {
+                Some(in_flight) => Some(
+                    in_flight
+                        .clone()
+                        .acquire_owned()
+                        .await
+                        .expect("the in-flight semaphore is never closed")
+                ),
+                None => None,
+            };
+
+        Permit {
+            _permit: permit,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    state: Mutex<BucketState>,
+}
+
+#[derive(Debug)]
+struct BucketState {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(requests_per_second: f64, burst: u32) -> Self {
+        let burst = burst.max(1) as f64;
+
+        Self {
+            rate: requests_per_second.max(f64::MIN_POSITIVE),
+            burst,
+            state: Mutex::new(
+                BucketState {
+                    tokens: burst,
+                    updated: Instant::now(),
+                }
+            ),
+        }
+    }
+
+    /// Reserves a token right away and sleeps until it becomes available, so waiters are served
+    /// in the order they arrived.
+    async fn acquire(&self) {
+        let wait = {
+            let mut state = self.state.lock().unwrap();
+            let now = Instant::now();
+
+            let elapsed = now.duration_since(state.updated).as_secs_f64();
+
+            state.tokens = (state.tokens + elapsed * self.rate).min(self.burst) - 1.0;
+            state.updated = now;
+
+            if state.tokens >= 0.0 {
+                None
+            } else {
+                Some(Duration::from_secs_f64(-state.tokens / self.rate))
+            }
+        };
+
+        if let Some(wait) = wait {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time::Instant;
+
+    use super::RateLimiter;
+
+    #[tokio::test(start_paused = true)]
+    async fn token_bucket_spaces_requests() {
+        let limiter = RateLimiter::unlimited().with_rate(10.0, 2);
+        let start = Instant::now();
+
+        let mut elapsed = Vec::new();
+
+        for _ in 0..5 {
+            limiter.acquire().await;
+            elapsed.push(start.elapsed().as_millis());
+        }
+
+        assert_eq!(elapsed, vec![0, 0, 100, 200, 300]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn clones_share_the_bucket() {
+        let limiter = RateLimiter::unlimited().with_rate(1.0, 1);
+        let other = limiter.clone();
+        let start = Instant::now();
+
+        limiter.acquire().await;
+        other.acquire().await;
+
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn caps_requests_in_flight() {
+        let limiter = RateLimiter::unlimited().with_max_in_flight(2);
+
+        let first = limiter.acquire().await;
+        let _second = limiter.clone().acquire().await;
+
+        assert!(tokio::time::timeout(Duration::from_secs(1), limiter.acquire()).await.is_err());
+
+        drop(first);
+
+        assert!(tokio::time::timeout(Duration::from_secs(1), limiter.acquire()).await.is_ok());
+    }
+}
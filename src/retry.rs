@@ -0,0 +1,227 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+
+/// Decides whether and when a failed request is sent again.
+///
+/// Only responses with one of `retry_statuses` and connect or timeout errors are retried.
+/// Calls that are not idempotent are sent once unless `retry_non_idempotent` is set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one.
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub multiplier: f64,
+    /// Picks a random delay between zero and the computed backoff ("full jitter").
+    pub jitter: bool,
+    /// Waits as long as the `Retry-After` header asks (capped at `max_backoff`).
+    pub respect_retry_after: bool,
+    pub retry_statuses: Vec<u16>,
+    pub retry_non_idempotent: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: true,
+            respect_retry_after: true,
+            retry_statuses: vec![408, 429, 500, 502, 503, 504],
+            retry_non_idempotent: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sends every request exactly once.
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    pub fn with_initial_backoff(mut self, initial_backoff: Duration) -> Self {
+        self.initial_backoff = initial_backoff;
+        self
+    }
+
+    pub fn with_max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    pub fn with_multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    pub fn with_jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    pub fn with_respect_retry_after(mut self, respect_retry_after: bool) -> Self {
+        self.respect_retry_after = respect_retry_after;
+        self
+    }
+
+    pub fn with_retry_statuses(mut self, retry_statuses: &[u16]) -> Self {
+        self.retry_statuses = retry_statuses.to_vec();
+        self
+    }
+
+    pub fn with_retry_non_idempotent(mut self, retry_non_idempotent: bool) -> Self {
+        self.retry_non_idempotent = retry_non_idempotent;
+        self
+    }
+
+    /// Whether another attempt may follow attempt number `attempt` (starting at 1).
+    pub fn allows_retry(&self, attempt: u32, idempotent: bool) -> bool {
+        attempt < self.max_attempts
+            && (idempotent || self.retry_non_idempotent)
+    }
+
+    pub fn retries_status(&self, status: u16) -> bool {
+        self.retry_statuses.contains(&status)
+    }
+
+    pub fn retries_error(&self, error: &reqwest::Error) -> bool {
+        error.is_connect() || error.is_timeout()
+    }
+
+    /// The exponential backoff before the attempt following attempt number `attempt`.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+
+        let backoff =
+            (self.initial_backoff.as_secs_f64() * self.multiplier.powi(exponent))
+                .min(self.max_backoff.as_secs_f64());
+
+        let backoff = Duration::from_secs_f64(backoff.max(0.0));
+
+        if self.jitter {
+            backoff.mul_f64(random_fraction())
+        } else {
+            backoff
+        }
+    }
+
+    /// The delay before the next attempt, preferring the server's `Retry-After` when allowed.
+    pub fn delay(&self, attempt: u32, headers: Option<&HeaderMap>) -> Duration {
+        let retry_after =
+            headers
+                .filter(|_| self.respect_retry_after)
+                .and_then(retry_after);
+
+        match retry_after {
+            Some(retry_after) => retry_after.min(self.max_backoff),
+            None => self.backoff(attempt),
+        }
+    }
+}
+
+/// Reads a `Retry-After` header given in seconds. HTTP dates are not supported.
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    headers
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+fn random_fraction() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+
+    hasher.write_u64(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default()
+    );
+
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use reqwest::header::{HeaderMap, HeaderValue, RETRY_AFTER};
+
+    use super::RetryPolicy;
+
+    #[test]
+    fn exponential_backoff() {
+        let policy =
+            RetryPolicy::new()
+                .with_initial_backoff(Duration::from_millis(100))
+                .with_max_backoff(Duration::from_millis(350))
+                .with_jitter(false);
+
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(350));
+    }
+
+    #[test]
+    fn jitter_stays_below_backoff() {
+        let policy =
+            RetryPolicy::new()
+                .with_initial_backoff(Duration::from_millis(100));
+
+        for attempt in 1..10 {
+            assert!(policy.backoff(attempt) <= Duration::from_millis(100 << (attempt - 1)).min(policy.max_backoff));
+        }
+    }
+
+    #[test]
+    fn honors_retry_after() {
+        let policy =
+            RetryPolicy::new()
+                .with_max_backoff(Duration::from_secs(10))
+                .with_jitter(false);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("4"));
+
+        assert_eq!(policy.delay(1, Some(&headers)), Duration::from_secs(4));
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("120"));
+
+        assert_eq!(policy.delay(1, Some(&headers)), Duration::from_secs(10));
+        assert_eq!(
+            policy.clone().with_respect_retry_after(false).delay(1, Some(&headers)),
+            policy.initial_backoff
+        );
+    }
+
+    #[test]
+    fn idempotency() {
+        let policy = RetryPolicy::new().with_max_attempts(2);
+
+        assert!(policy.allows_retry(1, true));
+        assert!(!policy.allows_retry(2, true));
+        assert!(!policy.allows_retry(1, false));
+        assert!(policy.with_retry_non_idempotent(true).allows_retry(1, false));
+        assert!(!RetryPolicy::none().allows_retry(1, true));
+    }
+}
@@ -2,24 +2,293 @@ use std::ops::{Add, Div, Mul};
 
 use serde::{Deserialize, Serialize};
 
-use crate::Fingerprint;
+use crate::{BitFingerprint, Error, Fingerprint, RetinaShape};
 
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// Similarity of two binary fingerprints, computed from |A|, |B| and |A ∩ B| alone.
+///
+/// Every metric here is the closed form of the same metric over the two expanded 0/1 vectors of
+/// `len` positions, so nothing is ever expanded. Use [`WeightedSimilarity`] for vectors with
+/// values other than 0 and 1.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct FingerprintSimilarity {
-    pub exp_vec_left: Vec<f64>,
-    pub exp_vec_right: Vec<f64>,
+    pub size_left: u32,
+    pub size_right: u32,
+    pub overlap: u32,
+    /// The number of positions on the retina.
+    pub len: usize,
 }
 
 impl FingerprintSimilarity {
-    pub fn new(left: &Fingerprint, right: &Fingerprint) -> Self {
-        let exp_vec_left = left.expand_t::<f64>(128 * 128);
-        let exp_vec_right = right.expand_t::<f64>(128 * 128);
+    /// Compares two fingerprints of the default 128x128 retina.
+    pub fn new(left: &Fingerprint, right: &Fingerprint) -> Result<Self, Error> {
+        Self::with_shape(left, right, RetinaShape::default())
+    }
+
+    pub fn with_shape(left: &Fingerprint, right: &Fingerprint, shape: RetinaShape) -> Result<Self, Error> {
+        let left = sorted_positions(left, shape)?;
+        let right = sorted_positions(right, shape)?;
+
+        Ok(Self::from_counts(left.len() as u32, right.len() as u32, sorted_overlap(&left, &right), shape.len()))
+    }
 
+    pub fn from_bits(left: &BitFingerprint, right: &BitFingerprint) -> Self {
+        Self::from_counts(left.len(), right.len(), left.overlap(right), BitFingerprint::LEN)
+    }
+
+    /// Takes the counts as they are. `overlap` should not exceed either size; counts that break
+    /// this give meaningless scores, but no metric panics or wraps around on them.
+    pub fn from_counts(size_left: u32, size_right: u32, overlap: u32, len: usize) -> Self {
         Self {
-            exp_vec_left,
-            exp_vec_right,
+            size_left,
+            size_right,
+            overlap,
+            len,
+        }
+    }
+
+    #[inline(always)]
+    pub fn size_left(&self) -> u32 {
+        self.size_left
+    }
+
+    #[inline(always)]
+    pub fn size_right(&self) -> u32 {
+        self.size_right
+    }
+
+    #[inline(always)]
+    pub fn euclidean_distance(&self) -> f64 {
+        (self.hamming_distance() as f64)
+            .sqrt()
+    }
+
+    /// |A ∩ B| / |A ∪ B|, or 0 when both fingerprints are empty.
+    #[inline(always)]
+    pub fn jaccard_index(&self) -> f64 {
+        ratio(self.overlap as f64, self.size_left as f64 + self.size_right as f64 - self.overlap as f64)
+    }
+
+    #[inline(always)]
+    pub fn jaccard_distance(&self) -> f64 {
+        1.0 - self.jaccard_index()
+    }
+
+    /// The number of positions set in exactly one of the two fingerprints.
+    #[inline(always)]
+    pub fn hamming_distance(&self) -> u64 {
+        (self.size_left as i64 + self.size_right as i64 - 2 * self.overlap as i64).max(0) as u64
+    }
+
+    /// The Sørensen–Dice coefficient 2|A ∩ B| / (|A| + |B|), or 0 when both are empty.
+    #[inline(always)]
+    pub fn dice(&self) -> f64 {
+        ratio(2.0 * self.overlap as f64, self.size_left as f64 + self.size_right as f64)
+    }
+
+    /// The Sørensen distance 1 - dice, also known as the Bray–Curtis dissimilarity of two sets.
+    #[inline(always)]
+    pub fn sorensen_distance(&self) -> f64 {
+        1.0 - self.dice()
+    }
+
+    /// The Tversky index |A ∩ B| / (|A ∩ B| + α|A - B| + β|B - A|). α = β = 1 gives Jaccard,
+    /// α = β = 0.5 gives Dice. 0 when the denominator is.
+    #[inline(always)]
+    pub fn tversky(&self, alpha: f64, beta: f64) -> f64 {
+        let overlap = self.overlap as f64;
+
+        ratio(
+            overlap,
+            overlap
+                + alpha * (self.size_left as f64 - overlap)
+                + beta * (self.size_right as f64 - overlap),
+        )
+    }
+
+    /// The overlap (Szymkiewicz–Simpson) coefficient |A ∩ B| / min(|A|, |B|), or 0 when either
+    /// is empty.
+    #[inline(always)]
+    pub fn overlap_coefficient(&self) -> f64 {
+        ratio(self.overlap as f64, self.size_left.min(self.size_right) as f64)
+    }
+
+    /// n² times the covariance of the two 0/1 vectors, and n² times the sum of their variances,
+    /// both exact.
+    #[inline(always)]
+    fn covariance_terms(&self) -> (i128, i128) {
+        let (a, b, c, n) = (self.size_left as i128, self.size_right as i128, self.overlap as i128, self.len as i128);
+
+        (n * c - a * b, a * (n - a) + b * (n - b))
+    }
+
+    #[inline(always)]
+    pub fn normalized_euclidean_distance(&self) -> f64 {
+        1.0 - self.normalized_euclidean_similarity()
+    }
+
+    /// sum((x - mean(x)) - (y - mean(y)))^2 / (sum(x - mean(x))^2 + sum(y - mean(y))^2), subtracted
+    /// from 1, which reduces to 2 cov(x, y) / (var(x) + var(y)).
+    #[inline(always)]
+    pub fn normalized_euclidean_similarity(&self) -> f64 {
+        let (covariance, variances) = self.covariance_terms();
+
+        (2 * covariance) as f64 / variances as f64
+    }
+
+    /// The cosine shifted into 0..1.
+    #[inline(always)]
+    pub fn cosine_similarity(&self) -> f64 {
+        (self.overlap as f64 / (self.size_left as f64 * self.size_right as f64).sqrt())
+            .add(1.0)
+            .div(2.0)
+    }
+
+    #[inline(always)]
+    pub fn overlapping_all(&self) -> u64 {
+        self.overlap as u64
+    }
+
+    #[inline(always)]
+    pub fn weighted_scoring(
+        &self,
+    ) -> f64 {
+        self.cosine_similarity()
+            .mul(self.normalized_euclidean_similarity())
+            .sqrt()
+    }
+
+    #[inline(always)]
+    pub fn pearson_r_coeff(&self) -> f64 {
+        let (a, b, n) = (self.size_left as f64, self.size_right as f64, self.len as f64);
+        let (covariance, _) = self.covariance_terms();
+
+        (covariance as f64)
+            .div((a * (n - a) * b * (n - b)).sqrt())
+    }
+
+    /// The `/compare` metrics that follow from the counts, see [`LocalCompare`].
+    pub fn local_compare(&self) -> LocalCompare {
+        let size_left = self.size_left();
+        let size_right = self.size_right();
+        let overlap = self.overlap;
+
+        LocalCompare {
+            size_left,
+            size_right,
+            overlapping_all: overlap,
+            overlapping_left_right: ratio(overlap as f64, size_left as f64),
+            overlapping_right_left: ratio(overlap as f64, size_right as f64),
+            jaccard_distance: self.jaccard_distance(),
+            cosine: ratio(overlap as f64, (size_left as f64 * size_right as f64).sqrt()),
         }
     }
+}
+
+/// The metrics of a [`CompareResponse`](crate::CompareResponse) that follow from |A|, |B| and
+/// |A ∩ B|, computed without calling the API.
+///
+/// The API's weighted scoring and euclidean distance have no counterpart here: it doesn't
+/// document how it computes them, and neither follows from the counts.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LocalCompare {
+    pub size_left: u32,
+    pub size_right: u32,
+    pub overlapping_all: u32,
+    /// The share of the left positions that the right fingerprint has too.
+    pub overlapping_left_right: f64,
+    /// The share of the right positions that the left fingerprint has too.
+    pub overlapping_right_left: f64,
+    pub jaccard_distance: f64,
+    /// The plain cosine of the two binary vectors, in 0..1 like the API's `cosineSimilarity`.
+    /// Unlike [`FingerprintSimilarity::cosine_similarity`], it is not shifted by `(x + 1) / 2`.
+    pub cosine: f64,
+}
+
+pub(crate) fn ratio(numerator: f64, denominator: f64) -> f64 {
+    if denominator == 0.0 {
+        0.0
+    } else {
+        numerator / denominator
+    }
+}
+
+/// The positions of `fingerprint`, sorted and deduplicated, checked against `shape`.
+pub(crate) fn sorted_positions(fingerprint: &Fingerprint, shape: RetinaShape) -> Result<Vec<u32>, Error> {
+    if let Some(position) = fingerprint.positions.iter().find(|position| !shape.contains(**position)) {
+        return Err(Error::PositionOutOfRange { position: *position, len: shape.len() });
+    }
+
+    let mut positions = fingerprint.positions.clone();
+
+    positions.sort_unstable();
+    positions.dedup();
+
+    Ok(positions)
+}
+
+pub(crate) fn sorted_overlap(left: &[u32], right: &[u32]) -> u32 {
+    let (mut i, mut j, mut overlap) = (0, 0, 0);
+
+    while i < left.len() && j < right.len() {
+        match left[i].cmp(&right[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                overlap += 1;
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+
+    overlap
+}
+
+/// Similarity of two dense vectors, one value per retina position.
+///
+/// Meant for weighted vectors such as densities or aggregated fingerprints; for two binary
+/// fingerprints every metric equals the one [`FingerprintSimilarity`] computes from counts.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WeightedSimilarity {
+    pub exp_vec_left: Vec<f64>,
+    pub exp_vec_right: Vec<f64>,
+}
+
+impl WeightedSimilarity {
+    pub fn new(exp_vec_left: Vec<f64>, exp_vec_right: Vec<f64>) -> Result<Self, Error> {
+        if exp_vec_left.len() != exp_vec_right.len() {
+            return Err(Error::ShapeMismatch { expected: exp_vec_left.len(), actual: exp_vec_right.len() });
+        }
+
+        Ok(
+            Self {
+                exp_vec_left,
+                exp_vec_right,
+            }
+        )
+    }
+
+    /// Expands both fingerprints onto `shape`.
+    pub fn from_fingerprints(left: &Fingerprint, right: &Fingerprint, shape: RetinaShape) -> Result<Self, Error> {
+        Self::new(left.expand_t_with_shape::<f64>(shape)?, right.expand_t_with_shape::<f64>(shape)?)
+    }
+
+    /// The number of non-zero values on the left.
+    #[inline(always)]
+    pub fn size_left(&self) -> u32 {
+        self.exp_vec_left
+            .iter()
+            .filter(|l| **l != 0.0)
+            .count() as u32
+    }
+
+    #[inline(always)]
+    pub fn size_right(&self) -> u32 {
+        self.exp_vec_right
+            .iter()
+            .filter(|r| **r != 0.0)
+            .count() as u32
+    }
 
     #[inline(always)]
     pub fn euclidean_distance(&self) -> f64 {
@@ -31,14 +300,36 @@ impl FingerprintSimilarity {
             .sqrt()
     }
 
+    /// The weighted (Ruzicka) Jaccard index sum(min(x, y)) / sum(max(x, y)), which is the plain
+    /// Jaccard index for 0/1 vectors. 0 when both vectors are all zero.
     #[inline(always)]
     pub fn jaccard_index(&self) -> f64 {
+        let (min, max) =
+            self.exp_vec_left
+                .iter()
+                .zip(self.exp_vec_right.iter())
+                .fold((0.0, 0.0), |(min, max), (l, r)| (min + l.min(*r), max + l.max(*r)));
+
+        if max == 0.0 {
+            0.0
+        } else {
+            min / max
+        }
+    }
+
+    #[inline(always)]
+    pub fn jaccard_distance(&self) -> f64 {
+        1.0 - self.jaccard_index()
+    }
+
+    /// The number of positions where the two vectors differ.
+    #[inline(always)]
+    pub fn hamming_distance(&self) -> u64 {
         self.exp_vec_left
             .iter()
             .zip(self.exp_vec_right.iter())
-            .map(|(l, r)| (l - r).abs())
-            .sum::<f64>()
-            .div(2.0)
+            .filter(|(l, r)| l != r)
+            .count() as u64
     }
 
     // normalized euclidean distance
@@ -101,6 +392,7 @@ impl FingerprintSimilarity {
         (sum / (left_sum * right_sum).sqrt()).add(1.0).div(2.0)
     }
 
+    /// The number of positions that are non-zero on both sides.
     #[inline(always)]
     pub fn overlapping_all(&self) -> u64 {
         let left_hs = &self.exp_vec_left;
@@ -109,7 +401,7 @@ impl FingerprintSimilarity {
         left_hs
             .iter()
             .zip(right_hs.iter())
-            .filter(|(l, r)| l.eq(r) && (**l).eq(&1.0))
+            .filter(|(l, r)| **l != 0.0 && **r != 0.0)
             .count() as u64
     }
 
@@ -127,17 +419,264 @@ impl FingerprintSimilarity {
         let left_mean = self.exp_vec_left.iter().sum::<f64>() / self.exp_vec_left.len() as f64;
         let right_mean = self.exp_vec_right.iter().sum::<f64>() / self.exp_vec_right.len() as f64;
 
-        let (r1, r2) =
+        let (covariance, left_variance, right_variance) =
             self.exp_vec_left
                 .iter()
                 .zip(self.exp_vec_right.iter())
-                .fold((0.0, 0.0), |(_s1, s2), (l, r)| {
+                .fold((0.0, 0.0, 0.0), |(sxy, sxx, syy), (l, r)| {
                     (
-                        s2.add((l - left_mean).mul(r - right_mean)),
-                        s2.add((l - left_mean).powi(2).mul((r - right_mean).powi(2))),
+                        sxy.add((l - left_mean).mul(r - right_mean)),
+                        sxx.add((l - left_mean).powi(2)),
+                        syy.add((r - right_mean).powi(2)),
                     )
                 });
 
-        r1.div(r2.sqrt())
+        covariance.div((left_variance * right_variance).sqrt())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use proptest::collection::btree_set;
+    use proptest::prelude::*;
+
+    use crate::{BitFingerprint, CompareResponse, Error, Fingerprint, RetinaShape};
+
+    use super::{FingerprintSimilarity, WeightedSimilarity};
+
+    fn fingerprint(positions: &[u32]) -> Fingerprint {
+        Fingerprint { positions: positions.to_vec() }
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn compare_full() {
+        let left = fingerprint(&[0, 1, 2, 3]);
+        let right = fingerprint(&[2, 3, 4, 5, 6, 16383]);
+
+        let compare = left.compare_full(&right).unwrap();
+
+        assert_eq!(compare.size_left, 4);
+        assert_eq!(compare.size_right, 6);
+        assert_eq!(compare.overlapping_all, 2);
+        assert_close(compare.overlapping_left_right, 0.5);
+        assert_close(compare.overlapping_right_left, 1.0 / 3.0);
+        assert_close(compare.jaccard_distance, 0.75);
+        assert_close(compare.cosine, 2.0 / 24f64.sqrt());
+        assert_close(left.compare_with_shape(&right, RetinaShape::default()).unwrap().cosine_similarity(), (2.0 / 24f64.sqrt() + 1.0) / 2.0);
+    }
+
+    #[test]
+    fn compare_full_extremes() {
+        let left = fingerprint(&[10, 20, 30]);
+
+        let same = left.compare_full(&left).unwrap();
+
+        assert_close(same.cosine, 1.0);
+        assert_close(same.jaccard_distance, 0.0);
+
+        let disjoint = left.compare_full(&fingerprint(&[40, 50])).unwrap();
+
+        assert_close(disjoint.cosine, 0.0);
+        assert_close(disjoint.jaccard_distance, 1.0);
+
+        let empty = left.compare_full(&fingerprint(&[])).unwrap();
+
+        assert_eq!(empty.size_right, 0);
+        assert_close(empty.cosine, 0.0);
+        assert_close(empty.overlapping_right_left, 0.0);
+    }
+
+    #[test]
+    fn compare_full_matches_compare_fixture_counts() {
+        let expected: CompareResponse = serde_json::from_str(include_str!("../fixtures/compare.json")).unwrap();
+
+        // 506 and 536 positions sharing 215, as in the fixture. The fixture is handwritten and its
+        // ratios don't follow from these counts (see fixtures/README.md), so only the counts are
+        // compared.
+        let left = fingerprint(&(0..506).collect::<Vec<_>>());
+        let right = fingerprint(&(291..827).collect::<Vec<_>>());
+
+        let compare = left.compare_full(&right).unwrap();
+
+        assert_eq!(compare.size_left, expected.size_left);
+        assert_eq!(compare.size_right, expected.size_right);
+        assert_eq!(compare.overlapping_all, expected.overlapping_all);
+    }
+
+    #[test]
+    fn custom_retina_shape() {
+        let shape = RetinaShape::new(4, 8);
+        let left = fingerprint(&[0, 31]);
+        let right = fingerprint(&[31]);
+
+        let similarity = left.compare_with_shape(&right, shape).unwrap();
+
+        assert_eq!(similarity.len, 32);
+        assert_eq!(similarity.overlapping_all(), 1);
+
+        assert!(matches!(
+            fingerprint(&[32]).compare_with_shape(&right, shape),
+            Err(Error::PositionOutOfRange { position: 32, len: 32 })
+        ));
+        assert!(matches!(
+            fingerprint(&[16384]).compare_full(&right),
+            Err(Error::PositionOutOfRange { position: 16384, len: 16384 })
+        ));
+    }
+
+    #[test]
+    fn counts_ignore_duplicates() {
+        let similarity = FingerprintSimilarity::new(&fingerprint(&[7, 3, 3, 1]), &fingerprint(&[3, 9, 7])).unwrap();
+
+        assert_eq!(similarity, FingerprintSimilarity::from_counts(3, 3, 2, 16384));
+
+        let bits = |positions: &[u32]| BitFingerprint::try_from(&fingerprint(positions)).unwrap();
+
+        assert_eq!(FingerprintSimilarity::from_bits(&bits(&[1, 3, 7]), &bits(&[3, 7, 9])), similarity);
+    }
+
+    #[test]
+    fn inconsistent_counts_do_not_underflow() {
+        let counts = FingerprintSimilarity::from_counts(1, 2, 5, 16);
+
+        assert_eq!(counts.hamming_distance(), 0);
+        assert!(counts.jaccard_index().is_finite());
+        assert!(counts.tversky(0.5, 0.5).is_finite());
+
+        let huge = FingerprintSimilarity::from_counts(u32::MAX, u32::MAX, 0, 16);
+
+        assert_eq!(huge.hamming_distance(), 2 * u32::MAX as u64);
+        assert_close(huge.dice(), 0.0);
+    }
+
+    #[test]
+    fn weighted_vectors_must_match() {
+        assert!(matches!(
+            WeightedSimilarity::new(vec![0.0; 3], vec![0.0; 4]),
+            Err(Error::ShapeMismatch { expected: 3, actual: 4 })
+        ));
+    }
+
+    #[test]
+    fn jaccard_and_hamming_reference_values() {
+        let similarity = FingerprintSimilarity::new(&fingerprint(&[1, 2, 3, 4]), &fingerprint(&[3, 4, 5, 6, 7])).unwrap();
+
+        // |{3, 4}| / |{1, ..., 7}|
+        assert_close(similarity.jaccard_index(), 2.0 / 7.0);
+        assert_close(similarity.jaccard_distance(), 5.0 / 7.0);
+        assert_eq!(similarity.hamming_distance(), 5);
+
+        let same = FingerprintSimilarity::new(&fingerprint(&[1, 2]), &fingerprint(&[1, 2])).unwrap();
+
+        assert_close(same.jaccard_index(), 1.0);
+        assert_eq!(same.hamming_distance(), 0);
+
+        let empty = FingerprintSimilarity::new(&fingerprint(&[]), &fingerprint(&[])).unwrap();
+
+        assert_close(empty.jaccard_index(), 0.0);
+
+        let weighted = WeightedSimilarity::new(vec![1.0, 2.0, 0.0, 4.0], vec![2.0, 1.0, 1.0, 4.0]).unwrap();
+
+        // (1 + 1 + 0 + 4) / (2 + 2 + 1 + 4)
+        assert_close(weighted.jaccard_index(), 6.0 / 9.0);
+        assert_eq!(weighted.hamming_distance(), 3);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn pearson_reference_values() {
+        // x = 1 1 0 0 0, y = 1 1 1 0 0: cov = 0.4 - 0.4 * 0.6, sd = sqrt(0.24) and sqrt(0.24)
+        let binary = FingerprintSimilarity::with_shape(&fingerprint(&[0, 1]), &fingerprint(&[0, 1, 2]), RetinaShape::new(1, 5)).unwrap();
+
+        assert_close(binary.pearson_r_coeff(), 2.0 / 3.0);
+
+        let uncorrelated = FingerprintSimilarity::with_shape(&fingerprint(&[0, 1]), &fingerprint(&[0, 2]), RetinaShape::new(2, 2)).unwrap();
+
+        assert_close(uncorrelated.pearson_r_coeff(), 0.0);
+
+        let inverse = FingerprintSimilarity::with_shape(&fingerprint(&[0, 1]), &fingerprint(&[2, 3]), RetinaShape::new(2, 2)).unwrap();
+
+        assert_close(inverse.pearson_r_coeff(), -1.0);
+
+        // the textbook example x = 1..5, y = 2 4 5 4 5 with r = 6 / sqrt(60)
+        let weighted = WeightedSimilarity::new(vec![1.0, 2.0, 3.0, 4.0, 5.0], vec![2.0, 4.0, 5.0, 4.0, 5.0]).unwrap();
+
+        assert_close(weighted.pearson_r_coeff(), 6.0 / 60f64.sqrt());
+        assert_close(WeightedSimilarity::new(vec![1.0, 2.0, 3.0], vec![3.0, 2.0, 1.0]).unwrap().pearson_r_coeff(), -1.0);
+    }
+
+    /// Equal up to the rounding of the dense sums, which `weighted_scoring`'s square root blows up
+    /// near zero (far enough that the dense product may even turn slightly negative).
+    fn agree(counted: f64, dense: f64) -> bool {
+        match (counted.is_nan(), dense.is_nan()) {
+            (true, true) => true,
+            (false, false) => (counted - dense).abs() <= 1e-7 * counted.abs().max(1.0),
+            (false, true) => counted.abs() <= 1e-7,
+            (true, false) => false,
+        }
+    }
+
+    type Metric<T> = fn(&T) -> f64;
+
+    fn check_agreement(left: &Fingerprint, right: &Fingerprint, shape: RetinaShape) -> Result<(), TestCaseError> {
+        let counted = FingerprintSimilarity::with_shape(left, right, shape).unwrap();
+        let dense = WeightedSimilarity::from_fingerprints(left, right, shape).unwrap();
+
+        prop_assert_eq!(counted.size_left(), dense.size_left());
+        prop_assert_eq!(counted.size_right(), dense.size_right());
+        prop_assert_eq!(counted.overlapping_all(), dense.overlapping_all());
+        prop_assert_eq!(counted.hamming_distance(), dense.hamming_distance());
+
+        let metrics: [(&str, Metric<FingerprintSimilarity>, Metric<WeightedSimilarity>); 8] = [
+            ("euclidean_distance", FingerprintSimilarity::euclidean_distance, WeightedSimilarity::euclidean_distance),
+            ("jaccard_index", FingerprintSimilarity::jaccard_index, WeightedSimilarity::jaccard_index),
+            ("jaccard_distance", FingerprintSimilarity::jaccard_distance, WeightedSimilarity::jaccard_distance),
+            ("normalized_euclidean_distance", FingerprintSimilarity::normalized_euclidean_distance, WeightedSimilarity::normalized_euclidean_distance),
+            ("normalized_euclidean_similarity", FingerprintSimilarity::normalized_euclidean_similarity, WeightedSimilarity::normalized_euclidean_similarity),
+            ("cosine_similarity", FingerprintSimilarity::cosine_similarity, WeightedSimilarity::cosine_similarity),
+            ("weighted_scoring", FingerprintSimilarity::weighted_scoring, WeightedSimilarity::weighted_scoring),
+            ("pearson_r_coeff", FingerprintSimilarity::pearson_r_coeff, WeightedSimilarity::pearson_r_coeff),
+        ];
+
+        for (name, counted_metric, dense_metric) in metrics {
+            let (counted, dense) = (counted_metric(&counted), dense_metric(&dense));
+
+            prop_assert!(agree(counted, dense), "{}: counted {} != dense {}", name, counted, dense);
+        }
+
+        Ok(())
+    }
+
+    fn positions(max: u32, len: usize) -> impl Strategy<Value = Fingerprint> {
+        btree_set(0..max, 0..len)
+            .prop_map(|positions| Fingerprint { positions: positions.into_iter().collect() })
+    }
+
+    proptest! {
+        #[test]
+        fn counted_metrics_match_dense_metrics(left in positions(16384, 800), right in positions(16384, 800)) {
+            check_agreement(&left, &right, RetinaShape::default())?;
+        }
+
+        /// A tiny retina reaches the edge cases: empty, full and identical fingerprints.
+        #[test]
+        fn counted_metrics_match_dense_metrics_on_small_retinas(left in positions(6, 7), right in positions(6, 7)) {
+            check_agreement(&left, &right, RetinaShape::new(2, 3))?;
+        }
+
+        #[test]
+        fn bitset_counts_match_position_counts(left in positions(16384, 800), right in positions(16384, 800)) {
+            let bits =
+                FingerprintSimilarity::from_bits(
+                    &BitFingerprint::try_from(&left).unwrap(),
+                    &BitFingerprint::try_from(&right).unwrap(),
+                );
+
+            prop_assert_eq!(bits, FingerprintSimilarity::new(&left, &right).unwrap());
+        }
+    }
+}
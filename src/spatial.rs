@@ -0,0 +1,310 @@
+//! Metrics that respect the topology of the retina.
+//!
+//! Semantic folding places related meanings next to each other, so a position one cell away from
+//! a position of the other fingerprint is a near miss rather than a total miss. These metrics
+//! implement [`SimilarityMetric`] like the count-based ones, but can't be scored from counts
+//! alone: their [`SimilarityMetric::score_counts`] is `None`.
+
+use std::collections::HashMap;
+
+use crate::metric::MetricKind;
+use crate::similarity::{ratio, sorted_overlap, FingerprintSimilarity};
+use crate::{BitFingerprint, Grid, RetinaShape, SimilarityMetric};
+
+/// The share of positions, over both fingerprints, that have a position of the other fingerprint
+/// at most `radius` rows and columns away. A radius of 0 is the Dice coefficient.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RadiusOverlap {
+    pub radius: u32,
+}
+
+impl RadiusOverlap {
+    pub fn new(radius: u32) -> Self {
+        Self {
+            radius,
+        }
+    }
+}
+
+impl SimilarityMetric for RadiusOverlap {
+    fn name(&self) -> &str {
+        "radius_overlap"
+    }
+
+    fn kind(&self) -> MetricKind {
+        MetricKind::Similarity
+    }
+
+    fn score_counts(&self, _: &FingerprintSimilarity) -> Option<f64> {
+        None
+    }
+
+    fn score(&self, a: &BitFingerprint, b: &BitFingerprint) -> f64 {
+        self.score_positions(&positions(a), &positions(b), RetinaShape::default())
+    }
+
+    fn score_positions(&self, a: &[u32], b: &[u32], shape: RetinaShape) -> f64 {
+        let matched = matched(a, b, self.radius as usize, shape) + matched(b, a, self.radius as usize, shape);
+
+        ratio(matched as f64, (a.len() + b.len()) as f64)
+    }
+
+    fn is_spatial(&self) -> bool {
+        true
+    }
+
+    fn radius(&self) -> Option<u32> {
+        Some(self.radius)
+    }
+}
+
+/// The cosine similarity of the fingerprints after blurring both with a Gaussian of `sigma`
+/// cells. The blur runs on an unbounded plane, so positions at the border lose nothing. A `sigma`
+/// of 0 is the plain cosine similarity of the two bit vectors, without the shift into 0..1 that
+/// [`FingerprintSimilarity::cosine_similarity`] applies.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlurredCosine {
+    pub sigma: f64,
+}
+
+impl BlurredCosine {
+    pub fn new(sigma: f64) -> Self {
+        Self {
+            sigma,
+        }
+    }
+}
+
+impl SimilarityMetric for BlurredCosine {
+    fn name(&self) -> &str {
+        "blurred_cosine"
+    }
+
+    fn kind(&self) -> MetricKind {
+        MetricKind::Similarity
+    }
+
+    fn score_counts(&self, _: &FingerprintSimilarity) -> Option<f64> {
+        None
+    }
+
+    fn score(&self, a: &BitFingerprint, b: &BitFingerprint) -> f64 {
+        self.score_positions(&positions(a), &positions(b), RetinaShape::default())
+    }
+
+    fn score_positions(&self, a: &[u32], b: &[u32], shape: RetinaShape) -> f64 {
+        if self.sigma <= 0.0 {
+            return ratio(sorted_overlap(a, b) as f64, (a.len() as f64 * b.len() as f64).sqrt());
+        }
+
+        // blurring both with G(σ) and taking the dot product sums G(σ√2) over every pair
+        let product = |x: &[u32], y: &[u32]| -> f64 {
+            x.iter()
+                .flat_map(|p| y.iter().map(move |q| squared_distance(*p, *q, shape)))
+                .map(|d| (-d / (4.0 * self.sigma * self.sigma)).exp())
+                .sum()
+        };
+
+        ratio(product(a, b), (product(a, a) * product(b, b)).sqrt())
+    }
+
+    fn is_spatial(&self) -> bool {
+        true
+    }
+}
+
+/// An approximation of the Earth Mover's Distance, in cells, between the fingerprints taken as
+/// two distributions of unit mass spread evenly over their positions.
+///
+/// The retina is covered with ever coarser grids of `2^l` cells per side. Mass that cannot be
+/// matched within the cells of level `l` has to travel about `2^(l - 1)` cells further. Every
+/// grid is also laid out shifted by half a cell to soften the cost of crossing cell borders. The
+/// estimate is 0 exactly for identical fingerprints, grows with the distance between them and
+/// tracks the true distance within a factor logarithmic in the retina size. It is NaN when
+/// exactly one fingerprint is empty.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EarthMovers;
+
+impl SimilarityMetric for EarthMovers {
+    fn name(&self) -> &str {
+        "earth_movers"
+    }
+
+    fn kind(&self) -> MetricKind {
+        MetricKind::Distance
+    }
+
+    fn score_counts(&self, _: &FingerprintSimilarity) -> Option<f64> {
+        None
+    }
+
+    fn score(&self, a: &BitFingerprint, b: &BitFingerprint) -> f64 {
+        self.score_positions(&positions(a), &positions(b), RetinaShape::default())
+    }
+
+    fn score_positions(&self, a: &[u32], b: &[u32], shape: RetinaShape) -> f64 {
+        match (a.is_empty(), b.is_empty()) {
+            (true, true) => return 0.0,
+            (true, false) | (false, true) => return f64::NAN,
+            (false, false) => {}
+        }
+
+        // every position of `a` weighs `b.len()` and vice versa, so the masses stay exact integers
+        let (mass_a, mass_b) = (b.len() as i64, a.len() as i64);
+
+        // one level past the whole retina, where even the shifted grid is a single cell
+        let levels = (shape.rows.max(shape.columns) as usize).next_power_of_two().trailing_zeros() + 1;
+
+        (0..=levels)
+            .map(|level| {
+                let shifts = if level == 0 { vec![0] } else { vec![0, 1 << (level - 1)] };
+
+                let unmatched =
+                    shifts
+                        .iter()
+                        .map(|shift| {
+                            let cell = |position: u32| {
+                                let (x, y) = shape.coordinates(position as usize);
+
+                                ((x + shift) >> level, (y + shift) >> level)
+                            };
+
+                            let mut cells: HashMap<(usize, usize), i64> = HashMap::new();
+
+                            for position in a {
+                                *cells.entry(cell(*position)).or_default() += mass_a;
+                            }
+
+                            for position in b {
+                                *cells.entry(cell(*position)).or_default() -= mass_b;
+                            }
+
+                            cells.values().map(|mass| mass.unsigned_abs()).sum::<u64>() as f64 / (2 * a.len() * b.len()) as f64
+                        })
+                        .sum::<f64>()
+                        / shifts.len() as f64;
+
+                unmatched * (1u64 << level.saturating_sub(1)) as f64
+            })
+            .sum()
+    }
+
+    fn is_spatial(&self) -> bool {
+        true
+    }
+}
+
+fn positions(fingerprint: &BitFingerprint) -> Vec<u32> {
+    fingerprint.iter().collect()
+}
+
+fn squared_distance(a: u32, b: u32, shape: RetinaShape) -> f64 {
+    let ((ax, ay), (bx, by)) = (shape.coordinates(a as usize), shape.coordinates(b as usize));
+
+    (ax as f64 - bx as f64).powi(2) + (ay as f64 - by as f64).powi(2)
+}
+
+/// How many positions of `from` have a position of `to` at most `radius` rows and columns away.
+fn matched(from: &[u32], to: &[u32], radius: usize, shape: RetinaShape) -> usize {
+    let mut active = Grid::filled(shape, false);
+
+    for position in to {
+        active.as_mut_slice()[*position as usize] = true;
+    }
+
+    from.iter()
+        .filter(|position| {
+            let (x, y) = active.coordinates(**position as usize);
+
+            active
+                .window(x, y, radius)
+                .any(|(_, active)| *active)
+        })
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metric::Dice;
+    use crate::similarity::FingerprintSimilarity;
+    use crate::{BitFingerprint, Fingerprint, RetinaShape, SimilarityMetric};
+
+    use super::{BlurredCosine, EarthMovers, RadiusOverlap};
+
+    const SHAPE: RetinaShape = RetinaShape { rows: 32, columns: 32 };
+
+    /// A fingerprint of the cells `(x, y)` of a 32x32 retina.
+    fn at(cells: &[(usize, usize)]) -> Fingerprint {
+        Fingerprint { positions: cells.iter().map(|(x, y)| SHAPE.position(*x, *y) as u32).collect() }
+    }
+
+    fn score(metric: &dyn SimilarityMetric, a: &Fingerprint, b: &Fingerprint) -> f64 {
+        metric.score_fingerprints(a, b, SHAPE).unwrap()
+    }
+
+    #[test]
+    fn radius_overlap_counts_near_misses() {
+        let (a, b) = (at(&[(0, 0), (10, 10)]), at(&[(0, 1), (20, 20)]));
+
+        assert_eq!(score(&RadiusOverlap::new(0), &a, &b), 0.0);
+        assert_eq!(score(&RadiusOverlap::new(1), &a, &b), 0.5);
+        assert_eq!(score(&RadiusOverlap::new(10), &a, &b), 1.0);
+
+        // radius 0 is Dice
+        let (c, d) = (at(&[(1, 1), (2, 2), (3, 3)]), at(&[(2, 2), (3, 3), (4, 4), (5, 5)]));
+
+        assert_eq!(score(&RadiusOverlap::new(0), &c, &d), score(&Dice, &c, &d));
+        assert_eq!(score(&RadiusOverlap::new(0), &c, &Fingerprint::default()), 0.0);
+    }
+
+    #[test]
+    fn blurred_cosine_decays_with_distance() {
+        let a = at(&[(10, 10), (10, 11)]);
+
+        let near = score(&BlurredCosine::new(1.0), &a, &at(&[(11, 10), (11, 11)]));
+        let far = score(&BlurredCosine::new(1.0), &a, &at(&[(13, 10), (13, 11)]));
+
+        assert!((score(&BlurredCosine::new(1.0), &a, &a) - 1.0).abs() < 1e-12);
+        assert!(near > far && far > 0.0, "{} {}", near, far);
+        assert!(score(&BlurredCosine::new(3.0), &a, &at(&[(13, 10), (13, 11)])) > far);
+
+        // without blur, the plain cosine of the bit vectors
+        assert_eq!(score(&BlurredCosine::new(0.0), &a, &at(&[(10, 11), (10, 12)])), 0.5);
+        assert_eq!(score(&BlurredCosine::new(1.0), &a, &Fingerprint::default()), 0.0);
+    }
+
+    #[test]
+    fn earth_movers_grows_with_distance() {
+        let a = at(&[(10, 10), (10, 11), (11, 10)]);
+
+        let shifted = |by: usize| at(&[(10, 10 + by), (10, 11 + by), (11, 10 + by)]);
+
+        assert_eq!(score(&EarthMovers, &a, &a), 0.0);
+
+        let distances = [1, 4, 16].map(|by| score(&EarthMovers, &a, &shifted(by)));
+
+        assert!(distances[0] > 0.0 && distances[0] < distances[1] && distances[1] < distances[2], "{:?}", distances);
+        assert_eq!(score(&EarthMovers, &shifted(4), &a), distances[1]);
+
+        // a lone point one cell away: unmatched at level 0, and in one of the two level 1 grids
+        assert_eq!(score(&EarthMovers, &at(&[(0, 0)]), &at(&[(0, 1)])), 1.5);
+
+        assert_eq!(score(&EarthMovers, &Fingerprint::default(), &Fingerprint::default()), 0.0);
+        assert!(score(&EarthMovers, &a, &Fingerprint::default()).is_nan());
+    }
+
+    #[test]
+    fn refuses_counts() {
+        let counts = FingerprintSimilarity::from_counts(4, 2, 1, 16);
+
+        for metric in [&RadiusOverlap::new(1) as &dyn SimilarityMetric, &BlurredCosine::new(1.0), &EarthMovers] {
+            assert!(metric.is_spatial());
+            assert_eq!(metric.score_counts(&counts), None, "{}", metric.name());
+        }
+
+        // bit fingerprints live on the default retina
+        let (a, b) = (BitFingerprint::try_from(&at(&[(0, 0)])).unwrap(), BitFingerprint::try_from(&at(&[(1, 0)])).unwrap());
+
+        assert_eq!(RadiusOverlap::new(1).score(&a, &b), 1.0);
+    }
+}